@@ -0,0 +1,20 @@
+// Emitter slots shared by the particle shaders, #include it after the #version line
+// MAX_EMITTERS is defined by the Rust side, see particles::MAX_EMITTERS
+#ifndef EMITTERS_GLSL
+#define EMITTERS_GLSL
+
+uniform int emitterCount;
+uniform int emitterEnd[MAX_EMITTERS];     // one past the last slot of each emitter, later slots are spare
+uniform vec4 emitterMotion[MAX_EMITTERS]; // x speed, y velocity cone angle in radians, z lifetime in seconds
+
+// Emitter owning a slot, -1 for spare slots
+int emitterOf(int slot) {
+    for (int i = 0; i < emitterCount; i++) {
+        if (slot < emitterEnd[i]) {
+            return i;
+        }
+    }
+    return -1;
+}
+
+#endif
@@ -5,26 +5,14 @@ in vec3 Normal;
 in vec3 FragPos;
 in vec2 TexCoords;
 
-struct Material {
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-    float shininess;
-};
-
-struct Light {
-    vec3 position;
-    vec3 ambient;
-    vec3 diffuse;
-    vec3 specular;
-};
+#include "lighting.glsl"
 
 uniform Material material;
 uniform Light light;
 uniform vec3 viewPos;
 uniform sampler2D textureSampler;
 
-varying vec3 barys;
+in vec3 barys;
 
 vec3 get_hex_center() {
     float X = ceil(barys.x) - barys.x;
@@ -40,7 +28,7 @@ vec3 get_hex_center() {
     else if (Z <= Y && Z <= X) {
         hex.z = ceil(barys.z);
     }
-    bool invalid_hex = (hex.x+hex.y+hex.z) != 10.0;
+    bool invalid_hex = (hex.x+hex.y+hex.z) != SUBDIVISIONS;
     if (invalid_hex) {
         if (X <= Y || X <= Z) {
             hex.x = ceil(barys.x);
@@ -57,26 +45,11 @@ vec3 get_hex_center() {
 
 void main()
 {
-    // Ambient
-    vec3 ambient = light.ambient * material.ambient;
-
-    // Diffuse
-    vec3 norm = normalize(Normal);
-    vec3 lightDir = normalize(light.position - FragPos);
-    float diff = max(dot(norm, lightDir), 0.0);
-    vec3 diffuse = light.diffuse * (diff * material.diffuse);
-
-    // Specular
-    vec3 viewDir = normalize(viewPos - FragPos);
-    vec3 reflectDir = reflect(-lightDir, norm);
-    float spec = pow(max(dot(viewDir, reflectDir), 0.0), material.shininess);
-    vec3 specular = light.specular * (spec * material.specular);
-
-    vec3 light = ambient + diffuse + specular;
+    vec3 light = phong(light, material, Normal, FragPos, viewPos);
 
-    vec2 uv = (TexCoords % vec2(1.0));
+    vec2 uv = mod(TexCoords, vec2(1.0));
 
-    vec3 result = texelFetch(textureSampler, ivec2(uv * vec2(1080.0, 540.0)), 0).rgb * light;
+    vec3 result = texelFetch(textureSampler, ivec2(uv * vec2(textureSize(textureSampler, 0))), 0).rgb * light;
 
 //    result = (vec3(TexCoords, 0.0) + result) / 2.0;
 //    result = vec3(TexCoords, 0.0);
@@ -0,0 +1,39 @@
+// Phong lighting, #include it after the #version line
+#ifndef LIGHTING_GLSL
+#define LIGHTING_GLSL
+
+struct Material {
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+    float shininess;
+};
+
+struct Light {
+    vec3 position;
+    vec3 ambient;
+    vec3 diffuse;
+    vec3 specular;
+};
+
+// Light reaching the eye from a surface point, multiply the surface colour with it
+vec3 phong(Light light, Material material, vec3 normal, vec3 fragPos, vec3 viewPos) {
+    // Ambient
+    vec3 ambient = light.ambient * material.ambient;
+
+    // Diffuse
+    vec3 norm = normalize(normal);
+    vec3 lightDir = normalize(light.position - fragPos);
+    float diff = max(dot(norm, lightDir), 0.0);
+    vec3 diffuse = light.diffuse * (diff * material.diffuse);
+
+    // Specular
+    vec3 viewDir = normalize(viewPos - fragPos);
+    vec3 reflectDir = reflect(-lightDir, norm);
+    float spec = pow(max(dot(viewDir, reflectDir), 0.0), material.shininess);
+    vec3 specular = light.specular * (spec * material.specular);
+
+    return ambient + diffuse + specular;
+}
+
+#endif
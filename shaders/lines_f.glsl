@@ -1,6 +1,28 @@
 #version 330
+in float ArcLength;
+in vec4 Color;
+flat in vec3 DashStyle;
+flat in float Fade;
+in float ViewDistance;
+
 out vec4 fragColor;
 
+uniform float time;      // seconds, moves the dashes along the curve
+uniform float fadeStart; // view distance where lines start to fade
+uniform float fadeEnd;   // view distance where lines are fully transparent
+
 void main() {
-    fragColor = vec4(1.0, 1.0, 1.0, 1.0); // Simple white color
+    float dashLength = DashStyle.x;
+    float gapLength = DashStyle.y;
+    float flowSpeed = DashStyle.z;
+
+    if (dashLength > 0.0) {
+        float phase = mod(ArcLength - time * flowSpeed, dashLength + gapLength);
+        if (phase > dashLength) {
+            discard;
+        }
+    }
+
+    float fade = 1.0 - Fade * smoothstep(fadeStart, fadeEnd, ViewDistance);
+    fragColor = vec4(Color.rgb, Color.a * fade);
 }
@@ -1,10 +1,27 @@
 #version 330
 layout (location = 0) in vec4 position;
+layout (location = 1) in float arcLength;
+layout (location = 2) in vec4 color;
+layout (location = 3) in vec3 dashStyle; // dash length, gap length, flow speed
+layout (location = 4) in float fade;      // 1 to fade with view distance, 0 to stay visible
+
+out float ArcLength;
+out vec4 Color;
+flat out vec3 DashStyle;
+flat out float Fade;
+out float ViewDistance;
 
 uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
+uniform vec3 viewPos;
 
 void main() {
-    gl_Position = projection * view * model * position;
+    vec4 worldPosition = model * position;
+    ArcLength = arcLength;
+    Color = color;
+    DashStyle = dashStyle;
+    Fade = fade;
+    ViewDistance = distance(viewPos, worldPosition.xyz);
+    gl_Position = projection * view * worldPosition;
 }
@@ -0,0 +1,9 @@
+#version 330
+in float shade;
+out vec4 fragColor;
+
+uniform vec3 color;
+
+void main() {
+    fragColor = vec4(color * shade, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 330
+layout (location = 0) in vec3 position;          // marker mesh, x forward, y up, z right
+layout (location = 1) in vec3 instancePosition;  // per marker, from the curve frame
+layout (location = 2) in vec3 instanceTangent;
+layout (location = 3) in vec3 instanceNormal;
+layout (location = 4) in vec3 instanceBinormal;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+
+out float shade;
+
+void main() {
+    mat3 orientation = mat3(instanceTangent, instanceNormal, instanceBinormal);
+    vec3 worldPosition = instancePosition + orientation * position;
+
+    shade = 0.6 + 0.4 * step(0.001, position.y); // lighter top so the orientation is visible
+    gl_Position = projection * view * model * vec4(worldPosition, 1.0);
+}
@@ -1,6 +1,23 @@
 #version 330
+in vec4 Color;
+in vec2 Corner;
 out vec4 fragColor;
 
+uniform bool useSprite;
+uniform sampler2D sprite;
+uniform float softness; // fraction of the radius over which the edge fades out
+
 void main() {
-    fragColor = vec4(1.0, 1.0, 1.0, 1.0); // Simple white color
+    float radius = length(Corner);
+    if (radius > 1.0) {
+        discard;
+    }
+
+    vec4 color = Color;
+    if (useSprite) {
+        color *= texture(sprite, Corner * 0.5 + 0.5);
+    } else {
+        color.a *= 1.0 - smoothstep(1.0 - softness, 1.0, radius);
+    }
+    fragColor = color;
 }
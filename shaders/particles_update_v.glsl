@@ -0,0 +1,152 @@
+#version 330
+#include "emitters.glsl"
+
+layout (location = 0) in vec4 inPosition; // xyz position, w age in seconds, negative while waiting to spawn
+layout (location = 1) in vec3 inVelocity; // the emitter owning the particle follows from its slot, see emitterEnd
+
+// captured with transform feedback into the other particle buffer, 7 floats per particle
+out vec4 outPosition;
+out vec3 outVelocity;
+
+uniform float deltaTime;        // seconds, one sub-step of the frame
+uniform float gravityMagnitude; // acceleration towards the gravity centre
+uniform vec3 gravityCenter;
+uniform float drag;             // fraction of velocity lost per second, applied exponentially
+
+uniform vec4 emitterShape[MAX_EMITTERS];  // xyz direction of the emitter centre, w spread angle in radians (pi for the whole surface)
+uniform float surfaceRadius;
+uniform uint randomSeed;        // changes every step so respawns don't repeat
+
+uniform int collisionMode;      // 0 off, 1 bounce, 2 stick on impact
+uniform float restitution;      // fraction of the normal velocity kept after a bounce
+uniform float friction;         // fraction of the tangential velocity lost on impact
+uniform bool useHeightmap;      // collide with the displaced terrain instead of the plain sphere
+uniform sampler2D heightmap;    // equirectangular, same mapping as the sphere texture
+uniform float heightScale;      // world units for a heightmap value of 1.0
+
+uniform bool useWind;           // advect along the surface with the wind field instead of gravity
+uniform sampler2D wind;         // RG32F, u eastward and v northward in m/s, see wind.rs for the layout
+uniform float windScale;        // world units per second for 1 m/s
+uniform float windAltitude;     // above surfaceRadius
+
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski, Olano 2020)
+uint pcg(uint v) {
+    uint state = v * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+float random(inout uint seed) {
+    seed = pcg(seed);
+    return float(seed) / 4294967295.0;
+}
+
+// Uniformly distributed direction within `angle` of `axis`
+vec3 randomInCone(vec3 axis, float angle, inout uint seed) {
+    float cosTheta = mix(1.0, cos(angle), random(seed));
+    float sinTheta = sqrt(max(0.0, 1.0 - cosTheta * cosTheta));
+    float phi = 6.28318530718 * random(seed);
+    vec3 helper = abs(axis.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(helper, axis));
+    vec3 bitangent = cross(axis, tangent);
+    return normalize(tangent * cos(phi) * sinTheta + bitangent * sin(phi) * sinTheta + axis * cosTheta);
+}
+
+// Radius of the ground under the given unit direction
+float surfaceHeightAt(vec3 direction) {
+    if (!useHeightmap) {
+        return surfaceRadius;
+    }
+    // same uv mapping as calculate_uv in icosahedron.rs
+    vec2 uv = vec2(0.5 + atan(direction.z, direction.x) / 6.28318530718, 0.5 - asin(direction.y) / 3.14159265359);
+    return surfaceRadius + heightScale * textureLod(heightmap, uv, 0.0).r;
+}
+
+// Bilinear between cell centres, by hand so it matches WindField::sample on the CPU
+vec2 windAt(float lon, float lat) {
+    ivec2 size = textureSize(wind, 0);
+    float x = (lon + 180.0) / 360.0 * float(size.x) - 0.5;
+    float y = clamp((90.0 - lat) / 180.0 * float(size.y) - 0.5, 0.0, float(size.y - 1));
+    vec2 cell = floor(vec2(x, y));
+    vec2 f = vec2(x, y) - cell;
+    int x0 = int(mod(cell.x, float(size.x)));
+    int x1 = int(mod(cell.x + 1.0, float(size.x)));
+    int y0 = int(cell.y);
+    int y1 = min(y0 + 1, size.y - 1);
+    vec2 top = mix(texelFetch(wind, ivec2(x0, y0), 0).rg, texelFetch(wind, ivec2(x1, y0), 0).rg, f.x);
+    vec2 bottom = mix(texelFetch(wind, ivec2(x0, y1), 0).rg, texelFetch(wind, ivec2(x1, y1), 0).rg, f.x);
+    return mix(top, bottom, f.y);
+}
+
+void main() {
+    int emitter = emitterOf(gl_VertexID);
+    if (emitter < 0) {
+        // not owned by an emitter, stays hidden
+        outPosition = inPosition;
+        outVelocity = inVelocity;
+        return;
+    }
+
+    float lifetime = emitterMotion[emitter].z;
+    float age = inPosition.w + deltaTime;
+    bool born = inPosition.w < 0.0 && age >= 0.0;
+    if (born || age >= lifetime) {
+        // respawn on the emitter, keeping the left over time so the spawn rate stays even
+        uint seed = pcg(uint(gl_VertexID) ^ pcg(randomSeed));
+        vec3 direction = randomInCone(normalize(emitterShape[emitter].xyz), emitterShape[emitter].w, seed);
+        vec3 velocity = randomInCone(direction, emitterMotion[emitter].y, seed) * emitterMotion[emitter].x;
+        outPosition = vec4(direction * surfaceRadius, mod(age, lifetime));
+        outVelocity = velocity;
+        return;
+    }
+    if (age < 0.0) {
+        // waiting to spawn
+        outPosition = vec4(inPosition.xyz, age);
+        outVelocity = inVelocity;
+        return;
+    }
+
+    if (useWind) {
+        // the velocity follows the field, the step is pulled back onto the sphere so it stays tangential
+        vec3 normal = normalize(inPosition.xyz);
+        float lon = atan(normal.z, normal.x);
+        float lat = asin(clamp(normal.y, -1.0, 1.0));
+        vec2 uv = windAt(degrees(lon), degrees(lat));
+        // derivatives of lat_lon_to_direction in curves.rs along longitude and latitude
+        vec3 east = vec3(-sin(lon), 0.0, cos(lon));
+        vec3 north = vec3(-sin(lat) * cos(lon), cos(lat), -sin(lat) * sin(lon));
+        vec3 windVelocity = (east * uv.x + north * uv.y) * windScale;
+        vec3 windPosition = normalize(inPosition.xyz + windVelocity * deltaTime) * (surfaceRadius + windAltitude);
+        outPosition = vec4(windPosition, age);
+        outVelocity = windVelocity;
+        return;
+    }
+
+    vec3 toCenter = gravityCenter - inPosition.xyz;
+    vec3 gravityDirection = length(toCenter) > 0.0 ? normalize(toCenter) : vec3(0.0);
+    vec3 gravity = gravityMagnitude * gravityDirection;
+    vec3 newVelocity = (inVelocity + gravity * deltaTime) * exp(-drag * deltaTime);
+
+    // Update position based on new velocity
+    vec3 newPosition = inPosition.xyz + newVelocity * deltaTime;
+
+    if (collisionMode != 0 && length(newPosition) > 0.0) {
+        vec3 normal = normalize(newPosition);
+        float ground = surfaceHeightAt(normal);
+        if (length(newPosition) < ground) {
+            // push back onto the surface, the sphere normal stands in for the terrain normal
+            newPosition = normal * ground;
+            float normalSpeed = dot(newVelocity, normal);
+            if (collisionMode == 2) {
+                newVelocity = vec3(0.0);
+            } else if (normalSpeed < 0.0) {
+                vec3 normalVelocity = normal * normalSpeed;
+                vec3 tangentVelocity = newVelocity - normalVelocity;
+                newVelocity = tangentVelocity * (1.0 - friction) - normalVelocity * restitution;
+            }
+        }
+    }
+
+    outPosition = vec4(newPosition, age);
+    outVelocity = newVelocity;
+}
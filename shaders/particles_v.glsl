@@ -1,26 +1,45 @@
 #version 330
-layout (location = 0) in vec4 inPosition;
-layout (location = 1) in vec4 inVelocity;  // Input for normals
+#include "emitters.glsl"
 
-out vec4 outPosition;
-out vec4 outVelocity;
+// per instance, each particle is one quad
+layout (location = 0) in vec4 position; // xyz position, w age
+layout (location = 1) in vec3 velocity;
 
 uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
 
-void main() {
-    float deltaTime = 0.016; // Assuming 60 FPS, for example
-    vec3 toOrigin = -vec3(inPosition);
-    vec3 gravityDirection = normalize(toOrigin);
-    float gravityMagnitude = 0.0981; // Example constant gravity magnitude
-    vec3 gravity = gravityMagnitude * gravityDirection;
-    vec3 newVelocity = vec3(inVelocity) + gravity * deltaTime;
+uniform vec4 emitterStartColor[MAX_EMITTERS];
+uniform vec4 emitterEndColor[MAX_EMITTERS];
+uniform vec2 emitterSize[MAX_EMITTERS];       // size in world units at birth and at death
+
+uniform bool colorBySpeed;  // wind particles, the emitter colours only give the alpha
+uniform vec4 slowColor;
+uniform vec4 fastColor;
+uniform float maxSpeed;     // world units per second that get fastColor
 
-    // Update position based on new velocity
-    outPosition = inPosition + vec4(newVelocity, 0.0) * deltaTime;
-    outVelocity = vec4(newVelocity, 0.0);
+out vec4 Color;
+out vec2 Corner; // -1..1 across the quad
+
+void main() {
+    // triangle strip corners (-1,-1) (1,-1) (-1,1) (1,1)
+    Corner = vec2(gl_VertexID & 1, gl_VertexID >> 1) * 2.0 - 1.0;
+    int emitter = emitterOf(gl_InstanceID);
+    if (emitter < 0 || position.w < 0.0) {
+        // spare or waiting to spawn, collapse the quad outside the clip volume
+        Color = vec4(0.0);
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        return;
+    }
 
-    // Transform position to clip space
-    gl_Position = projection * view * model * outPosition;
+    float t = clamp(position.w / emitterMotion[emitter].z, 0.0, 1.0);
+    Color = mix(emitterStartColor[emitter], emitterEndColor[emitter], t);
+    if (colorBySpeed) {
+        vec4 speedColor = mix(slowColor, fastColor, clamp(length(velocity) / maxSpeed, 0.0, 1.0));
+        Color = vec4(speedColor.rgb, speedColor.a * Color.a);
+    }
+    gl_Position = projection * view * model * vec4(position.xyz, 1.0);
+    // offset the corner in view space, which keeps the quad facing the camera and shrinking with distance
+    float worldSize = mix(emitterSize[emitter].x, emitterSize[emitter].y, t);
+    gl_Position.xy += Corner * worldSize * 0.5 * vec2(projection[0][0], projection[1][1]);
 }
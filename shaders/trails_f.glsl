@@ -0,0 +1,7 @@
+#version 330
+in vec4 Color;
+out vec4 fragColor;
+
+void main() {
+    fragColor = Color;
+}
@@ -0,0 +1,81 @@
+#version 330
+#include "emitters.glsl"
+
+// no vertex attributes, the instance is the particle and every two vertices are one snapshot along its trail
+
+uniform samplerBuffer trail; // trailLength layers of particle states, 7 floats per particle
+uniform int particleCount;
+uniform int trailLength;
+uniform int head;            // layer holding the newest snapshot
+uniform int filled;          // layers written so far
+uniform float trailWidth;    // pixels at the particle, tapering to nothing at the end
+uniform vec2 viewportSize;
+
+uniform mat4 model;
+uniform mat4 view;
+uniform mat4 projection;
+
+uniform vec4 emitterStartColor[MAX_EMITTERS];
+uniform vec4 emitterEndColor[MAX_EMITTERS];
+
+uniform bool colorBySpeed;  // wind particles, same colouring as in particles_v.glsl
+uniform vec4 slowColor;
+uniform vec4 fastColor;
+uniform float maxSpeed;
+
+out vec4 Color;
+
+// position + age of the particle `age` snapshots ago, its velocity in `velocity`
+vec4 snapshot(int particle, int age, out vec3 velocity) {
+    int layer = (head - age + trailLength) % trailLength;
+    int texel = (layer * particleCount + particle) * 7;
+    velocity = vec3(texelFetch(trail, texel + 4).r, texelFetch(trail, texel + 5).r, texelFetch(trail, texel + 6).r);
+    return vec4(texelFetch(trail, texel).r, texelFetch(trail, texel + 1).r, texelFetch(trail, texel + 2).r, texelFetch(trail, texel + 3).r);
+}
+
+vec2 toScreen(vec4 clip) {
+    return clip.xy / clip.w * viewportSize * 0.5;
+}
+
+void main() {
+    int particle = gl_InstanceID;
+    int age = gl_VertexID / 2;
+    float side = gl_VertexID % 2 == 0 ? -1.0 : 1.0;
+
+    vec3 newestVelocity;
+    vec3 velocity;
+    vec4 newest = snapshot(particle, 0, newestVelocity);
+    vec4 point = snapshot(particle, min(age, filled - 1), velocity);
+    int emitter = emitterOf(particle);
+
+    // ages only grow during one life, an older snapshot with a higher age belongs to a previous life
+    bool valid = age < filled && emitter >= 0 && emitter < emitterCount
+        && newest.w >= 0.0 && point.w >= 0.0 && point.w <= newest.w;
+
+    // direction of the trail on screen, from the neighbouring snapshot
+    bool hasOlder = age + 1 < filled;
+    vec3 neighbourVelocity;
+    vec4 neighbour = snapshot(particle, hasOlder ? age + 1 : max(age - 1, 0), neighbourVelocity);
+    vec4 clip = projection * view * model * vec4(point.xyz, 1.0);
+    vec4 neighbourClip = projection * view * model * vec4(neighbour.xyz, 1.0);
+    vec2 direction = hasOlder ? toScreen(clip) - toScreen(neighbourClip) : toScreen(neighbourClip) - toScreen(clip);
+    direction = length(direction) > 0.0 ? normalize(direction) : vec2(1.0, 0.0);
+    vec2 perpendicular = vec2(-direction.y, direction.x);
+
+    float fade = 1.0 - float(age) / float(max(trailLength - 1, 1));
+    vec2 offset = perpendicular * side * trailWidth * 0.5 * fade;
+    clip.xy += offset / (viewportSize * 0.5) * clip.w;
+
+    if (valid) {
+        float t = clamp(point.w / emitterMotion[emitter].z, 0.0, 1.0);
+        Color = mix(emitterStartColor[emitter], emitterEndColor[emitter], t);
+        if (colorBySpeed) {
+            vec4 speedColor = mix(slowColor, fastColor, clamp(length(velocity) / maxSpeed, 0.0, 1.0));
+            Color = vec4(speedColor.rgb, speedColor.a * Color.a);
+        }
+        Color.a *= fade;
+    } else {
+        Color = vec4(0.0);
+    }
+    gl_Position = clip;
+}
@@ -8,13 +8,13 @@ out vec3 FragPos;
 out vec3 Normal;
 out vec2 TexCoords;
 
-varying vec3 barys;
+out vec3 barys;
 
 uniform mat4 model;
 uniform mat4 view;
 uniform mat4 projection;
 
-const float SUBDIVISIONS = 10.0;
+// SUBDIVISIONS is defined from HEX_SUBDIVISIONS in hexgrid.rs
 
 void main()
 {
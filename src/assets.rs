@@ -0,0 +1,116 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::texture::{self, StreamingTexture};
+
+pub const ASSET_DIR_ENV: &str = "FLTK_OPENGL_TEST_ASSETS";
+const CHECKERBOARD_SIZE: (u32, u32) = (1024, 512); // equirectangular like the earth texture
+const CHECKERBOARD_SQUARE: u32 = 64;
+
+// Finds textures and data files by name, in the asset root or the copies built into the binary
+pub struct Assets {
+    roots: Vec<PathBuf>, // searched in order
+}
+
+impl Assets {
+    // `--assets <dir>` wins over the FLTK_OPENGL_TEST_ASSETS environment variable,
+    // without either the directory of the executable is searched first and then the working directory
+    pub fn from_env() -> Self {
+        let args: Vec<String> = env::args().collect();
+        let flag = args.iter().enumerate().find_map(|(i, arg)| match arg.strip_prefix("--assets") {
+            Some("") => args.get(i + 1).cloned(),
+            Some(rest) => rest.strip_prefix('=').map(str::to_string),
+            None => None,
+        });
+        let roots = match flag.map(PathBuf::from).or_else(|| env::var_os(ASSET_DIR_ENV).map(PathBuf::from)) {
+            Some(root) => vec![root],
+            None => {
+                let next_to_exe = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf));
+                next_to_exe.into_iter().chain(env::current_dir().ok()).collect()
+            }
+        };
+        Assets { roots }
+    }
+
+    // The first root that has the file
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        self.roots.iter().map(|root| root.join(name)).find(|path| path.is_file())
+    }
+
+    // Where to write a file, over the existing one or else in the first root
+    pub fn save_path(&self, name: &str) -> PathBuf {
+        self.path(name).or_else(|| self.roots.first().map(|root| root.join(name))).unwrap_or_else(|| PathBuf::from(name))
+    }
+
+    // Where the file comes from, without reading it yet
+    pub fn locate(&self, name: &str) -> io::Result<Source> {
+        match self.path(name) {
+            Some(path) => Ok(Source::File(path)),
+            None => embedded(name).map(Source::Embedded).ok_or_else(|| {
+                let searched: Vec<String> = self.roots.iter().map(|root| root.display().to_string()).collect();
+                io::Error::new(io::ErrorKind::NotFound, format!("{} is not in {} and not built in", name, searched.join(" or ")))
+            }),
+        }
+    }
+
+    // File contents, or the built in copy when the file isn't in any of the roots
+    pub fn read(&self, name: &str) -> io::Result<Cow<'static, [u8]>> {
+        self.locate(name)?.read()
+    }
+
+    pub fn read_to_string(&self, name: &str) -> io::Result<String> {
+        let bytes = self.read(name)?;
+        String::from_utf8(bytes.into_owned()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    // Text file run through a parser like wind::parse_wind
+    pub fn load<T>(&self, name: &str, parse: impl FnOnce(&str) -> Result<T, String>) -> Result<T, String> {
+        parse(&self.read_to_string(name).map_err(|e| e.to_string())?)
+    }
+
+    // Decoded on a worker thread, the texture is a placeholder until then and a preview during a few frames of uploading after,
+    // a missing or broken image gives a checkerboard and a warning, never a crash
+    pub fn texture(&self, name: &str) -> StreamingTexture {
+        let source = self.locate(name);
+        let name = name.to_string();
+        StreamingTexture::spawn(move || {
+            let image = source
+                .and_then(|source| source.read())
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| image::load_from_memory(&bytes).map_err(|e| e.to_string()));
+            image.unwrap_or_else(|e| {
+                eprintln!("Could not load texture {}: {}, using a checkerboard instead", name, e);
+                let (width, height) = CHECKERBOARD_SIZE;
+                texture::checkerboard(width, height, CHECKERBOARD_SQUARE)
+            })
+        })
+    }
+}
+
+pub enum Source {
+    File(PathBuf),
+    Embedded(&'static [u8]),
+}
+
+impl Source {
+    pub fn read(&self) -> io::Result<Cow<'static, [u8]>> {
+        match self {
+            Source::File(path) => fs::read(path).map(Cow::Owned),
+            Source::Embedded(bytes) => Ok(Cow::Borrowed(bytes)),
+        }
+    }
+}
+
+// Small assets that ship inside the binary so a bare executable still starts
+// the earth texture is too big for this, it falls back to the checkerboard
+fn embedded(name: &str) -> Option<&'static [u8]> {
+    match name {
+        "fltk.ico" => Some(include_bytes!("../fltk.ico")),
+        "satellites.tle" => Some(include_bytes!("../satellites.tle")),
+        "wind.txt" => Some(include_bytes!("../wind.txt")),
+        _ => None,
+    }
+}
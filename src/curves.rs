@@ -1,6 +1,41 @@
-use rand::Rng;
+use cgmath::{InnerSpace, Vector3};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-pub fn generate_random_bezier_points() -> Vec<(f32, f32)> {
+pub const CURVE_RADIUS: f32 = 2.03; // slightly above the sphere surface so lines don't z-fight with it
+
+// Constraints for the random road/river generator, angles and lengths are in degrees
+#[derive(Clone, Debug)]
+pub struct CurveConstraints {
+    pub segments: usize,
+    pub max_turn_angle: f32,   // max change of heading between two segments
+    pub min_segment_length: f32,
+    pub max_segment_length: f32,
+    pub min_lat: f32,
+    pub max_lat: f32,
+    pub min_lon: f32,
+    pub max_lon: f32,
+    pub max_attempts: usize,   // retries per segment before giving up on extending the curve
+}
+
+impl Default for CurveConstraints {
+    fn default() -> Self {
+        CurveConstraints {
+            segments: 12,
+            max_turn_angle: 35.0,
+            min_segment_length: 2.0,
+            max_segment_length: 6.0,
+            min_lat: -60.0,
+            max_lat: 60.0,
+            min_lon: -180.0,
+            max_lon: 180.0,
+            max_attempts: 16,
+        }
+    }
+}
+
+// Fixed example road, see generate_random_curve for actual random curves
+pub fn example_road_points() -> Vec<(f32, f32)> {
     // Predefined points for a smooth, curving road
     // These points are chosen to represent a gentle S-curve or similar road shapes
     Vec::from([
@@ -17,6 +52,113 @@ pub fn generate_random_bezier_points() -> Vec<(f32, f32)> {
     ])
 }
 
+// Random walk over the sphere, returns (lon, lat) points in degrees
+// the same seed and constraints always give the same curve
+// longitudes are continuous along the curve, a curve crossing the antimeridian goes past 180 instead of jumping to -180
+pub fn generate_random_curve(seed: u64, constraints: &CurveConstraints) -> Vec<(f32, f32)> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    random_curve(&mut rng, constraints)
+}
+
+pub fn generate_random_curves(seed: u64, count: usize, constraints: &CurveConstraints) -> Vec<Vec<(f32, f32)>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count).map(|_| random_curve(&mut rng, constraints)).collect()
+}
+
+fn random_curve(rng: &mut StdRng, c: &CurveConstraints) -> Vec<(f32, f32)> {
+    let start = (rng.gen_range(c.min_lon..=c.max_lon), rng.gen_range(c.min_lat..=c.max_lat));
+    let mut heading: f32 = rng.gen_range(0.0..360.0);
+    let mut points = vec![start];
+
+    for _ in 0..c.segments {
+        let last = *points.last().unwrap();
+        let mut next = None;
+        for _ in 0..c.max_attempts {
+            let turn = rng.gen_range(-c.max_turn_angle..=c.max_turn_angle);
+            let length = rng.gen_range(c.min_segment_length..=c.max_segment_length);
+            let candidate_heading = heading + turn;
+            let candidate = destination_point(last, candidate_heading, length);
+            if in_bounds(candidate, c) && !intersects_polyline(&points, last, candidate) {
+                next = Some((candidate, candidate_heading));
+                break;
+            }
+        }
+        // no valid continuation, the walk ran into the box edge or into itself
+        let Some((point, new_heading)) = next else { break };
+        points.push(point);
+        heading = new_heading;
+    }
+
+    points
+}
+
+// Point reached when travelling `distance` degrees of arc along a great circle from `start` with `heading` (0 = north)
+fn destination_point(start: (f32, f32), heading: f32, distance: f32) -> (f32, f32) {
+    let (lon, lat) = (start.0.to_radians(), start.1.to_radians());
+    let (heading, distance) = (heading.to_radians(), distance.to_radians());
+
+    let new_lat = (lat.sin() * distance.cos() + lat.cos() * distance.sin() * heading.cos()).asin();
+    // not wrapped, the longitude stays next to the start's so segments and interpolation don't jump across the globe
+    let new_lon = lon + (heading.sin() * distance.sin() * lat.cos()).atan2(distance.cos() - lat.sin() * new_lat.sin());
+    (new_lon.to_degrees(), new_lat.to_degrees())
+}
+
+// Longitude in -180..180
+fn wrap_lon(lon: f32) -> f32 {
+    (lon + 180.0).rem_euclid(360.0) - 180.0
+}
+
+// `lon` shifted by whole turns to lie within 180 degrees of `reference`
+fn unwrap_lon(lon: f32, reference: f32) -> f32 {
+    lon + ((reference - lon) / 360.0).round() * 360.0
+}
+
+fn in_bounds(point: (f32, f32), c: &CurveConstraints) -> bool {
+    let lon = wrap_lon(point.0);
+    lon >= c.min_lon && lon <= c.max_lon && point.1 >= c.min_lat && point.1 <= c.max_lat
+}
+
+// Check the new segment a-b against every earlier segment of the polyline except the one it connects to
+// segments are treated as straight lines in lon/lat space, good enough for the short segments of the generator
+// as long as the longitudes are unwrapped like random_curve keeps them
+fn intersects_polyline(points: &[(f32, f32)], a: (f32, f32), b: (f32, f32)) -> bool {
+    if points.len() < 3 {
+        return false;
+    }
+    points[..points.len() - 1].windows(2).any(|segment| segments_intersect(segment[0], segment[1], a, b))
+}
+
+fn segments_intersect(p1: (f32, f32), p2: (f32, f32), q1: (f32, f32), q2: (f32, f32)) -> bool {
+    let orientation = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+    let d1 = orientation(q1, q2, p1);
+    let d2 = orientation(q1, q2, p2);
+    let d3 = orientation(p1, p2, q1);
+    let d4 = orientation(p1, p2, q2);
+    (d1 > 0.0) != (d2 > 0.0) && (d3 > 0.0) != (d4 > 0.0)
+}
+
+// Convert (lon, lat) points in degrees to positions on the sphere, y is up like the camera in main
+// any longitude works, unwrapped ones end up in the same place as their -180..180 counterpart
+pub fn lat_lon_on_sphere(points: Vec<(f32, f32)>) -> Vec<(f32, f32, f32)> {
+    points.iter().map(|&(lon, lat)| {
+        let direction = lat_lon_to_direction(lon, lat);
+        (direction.x * CURVE_RADIUS, direction.y * CURVE_RADIUS, direction.z * CURVE_RADIUS)
+    }).collect()
+}
+
+// Unit vector pointing at (lon, lat) in degrees
+pub fn lat_lon_to_direction(lon: f32, lat: f32) -> Vector3<f32> {
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    Vector3::new(lat.cos() * lon.cos(), lat.sin(), lat.cos() * lon.sin())
+}
+
+// Inverse of lat_lon_on_sphere, works for any radius
+pub fn sphere_to_lat_lon(point: Vector3<f32>) -> (f32, f32) {
+    let lat = (point.y / point.magnitude()).asin();
+    let lon = point.z.atan2(point.x);
+    (lon.to_degrees(), lat.to_degrees())
+}
+
 pub fn points_on_sphere(points: Vec<(f32, f32)>) -> Vec<(f32, f32, f32)> {
     let max_theta = 80.0;  // Maximum extent of theta (longitude)
     let max_phi = 25.0;    // Maximum extent of phi (latitude)
@@ -31,12 +173,18 @@ pub fn points_on_sphere(points: Vec<(f32, f32)>) -> Vec<(f32, f32, f32)> {
         let y = theta.sin() * phi.sin();
         let z = phi.cos();
 
-        (x * 2.03, y * 2.03, z * 2.03)
+        (x * CURVE_RADIUS, y * CURVE_RADIUS, z * CURVE_RADIUS)
     }).collect()
 }
 
 
+// Smooth curve through (lon, lat) points, interpolated in lon/lat
+// the longitudes are unwrapped first so a segment over the antimeridian takes the short way round
 pub fn bezier_curve_interpolation(points: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    let mut points = points;
+    for i in 1..points.len() {
+        points[i].0 = unwrap_lon(points[i].0, points[i - 1].0);
+    }
     let mut curve_points = Vec::new();
     let num_segments = 10;  // Uniform number of segments for each curve
 
@@ -75,3 +223,175 @@ fn bezier_point(t: f32, p0: f32, p1: f32, p2: f32, p3: f32) -> f32 {
         (3.0 * one_minus_t * t.powi(2) * p2) +
         (t.powi(3) * p3)
 }
+
+// Position plus orientation on a curve, tangent points along the direction of travel
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub position: Vector3<f32>,
+    pub tangent: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub binormal: Vector3<f32>, // tangent x normal
+}
+
+// Curve on the sphere with arc length and rotation-minimizing frames precomputed per point
+pub struct CurvePath {
+    distances: Vec<f32>, // cumulative arc length at each point
+    frames: Vec<Frame>,
+}
+
+impl CurvePath {
+    pub fn new(points: &[(f32, f32, f32)]) -> Self {
+        // the bezier interpolation repeats the shared end point of each segment, drop zero length steps
+        let mut unique: Vec<Vector3<f32>> = Vec::with_capacity(points.len());
+        for &(x, y, z) in points {
+            let p = Vector3::new(x, y, z);
+            if unique.last().map_or(true, |last| (p - *last).magnitude2() > 1e-12) {
+                unique.push(p);
+            }
+        }
+
+        let mut distances = Vec::with_capacity(unique.len());
+        let mut total = 0.0;
+        for (i, p) in unique.iter().enumerate() {
+            if i > 0 {
+                total += (p - unique[i - 1]).magnitude();
+            }
+            distances.push(total);
+        }
+
+        let frames = rotation_minimizing_frames(&unique);
+        CurvePath { distances, frames }
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.distances.last().unwrap_or(&0.0)
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // Frame at the given arc length, clamped to the ends of the curve
+    pub fn frame_at(&self, distance: f32) -> Frame {
+        if self.frames.len() < 2 {
+            return self.frames.first().copied().unwrap_or(Frame {
+                position: Vector3::new(0.0, 0.0, 0.0),
+                tangent: Vector3::unit_x(),
+                normal: Vector3::unit_y(),
+                binormal: Vector3::unit_z(),
+            });
+        }
+        let distance = distance.max(0.0).min(self.length());
+        let next = self.distances.partition_point(|&d| d < distance).max(1).min(self.frames.len() - 1);
+        let (a, b) = (&self.frames[next - 1], &self.frames[next]);
+        let span = self.distances[next] - self.distances[next - 1];
+        let t = if span > 0.0 { (distance - self.distances[next - 1]) / span } else { 0.0 };
+
+        let position = a.position + (b.position - a.position) * t;
+        let tangent = (a.tangent + (b.tangent - a.tangent) * t).normalize();
+        // re-orthogonalize the interpolated normal against the interpolated tangent
+        let normal = a.normal + (b.normal - a.normal) * t;
+        let normal = (normal - tangent * normal.dot(tangent)).normalize();
+        Frame { position, tangent, normal, binormal: tangent.cross(normal) }
+    }
+}
+
+// Double reflection method (Wang et al. 2008) for rotation-minimizing (parallel transport) frames
+// the first normal is the surface normal; for a curve lying on a sphere the surface normal is itself
+// rotation-minimizing, so the transported normal keeps pointing away from the sphere without twisting
+fn rotation_minimizing_frames(points: &[Vector3<f32>]) -> Vec<Frame> {
+    let n = points.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let tangents: Vec<Vector3<f32>> = (0..n).map(|i| {
+        let before = points[i.saturating_sub(1)];
+        let after = points[(i + 1).min(n - 1)];
+        let d = after - before;
+        if d.magnitude2() > 0.0 { d.normalize() } else { Vector3::unit_x() }
+    }).collect();
+
+    let mut normals = Vec::with_capacity(n);
+    let up = if points[0].magnitude2() > 0.0 { points[0].normalize() } else { Vector3::unit_y() };
+    let first = up - tangents[0] * up.dot(tangents[0]);
+    normals.push(if first.magnitude2() > 1e-12 { first.normalize() } else { tangents[0].cross(Vector3::unit_z()).normalize() });
+
+    for i in 0..n - 1 {
+        let v1 = points[i + 1] - points[i];
+        let c1 = v1.dot(v1);
+        let r = normals[i];
+        let (r_l, t_l) = if c1 > 0.0 {
+            (r - v1 * (2.0 / c1 * v1.dot(r)), tangents[i] - v1 * (2.0 / c1 * v1.dot(tangents[i])))
+        } else {
+            (r, tangents[i])
+        };
+        let v2 = tangents[i + 1] - t_l;
+        let c2 = v2.dot(v2);
+        let next = if c2 > 1e-12 { r_l - v2 * (2.0 / c2 * v2.dot(r_l)) } else { r_l };
+        normals.push(next.normalize());
+    }
+
+    (0..n).map(|i| Frame {
+        position: points[i],
+        tangent: tangents[i],
+        normal: normals[i],
+        binormal: tangents[i].cross(normals[i]),
+    }).collect()
+}
+
+// Douglas-Peucker on the sphere, distances are angles (radians) seen from the centre of the sphere
+// so the tolerance doesn't depend on the radius the curve is drawn at
+pub fn simplify_on_sphere(points: &[(f32, f32, f32)], tolerance: f32) -> Vec<(f32, f32, f32)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let directions: Vec<Vector3<f32>> = points.iter().map(|&(x, y, z)| Vector3::new(x, y, z).normalize()).collect();
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+
+    // explicit stack instead of recursion, imported routes can have thousands of points
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let mut max_distance = 0.0;
+        let mut max_index = first;
+        for i in first + 1..last {
+            let distance = angular_distance_to_arc(directions[i], directions[first], directions[last]);
+            if distance > max_distance {
+                max_distance = distance;
+                max_index = i;
+            }
+        }
+        if max_distance > tolerance {
+            keep[max_index] = true;
+            stack.push((first, max_index));
+            stack.push((max_index, last));
+        }
+    }
+
+    points.iter().zip(keep).filter(|(_, keep)| *keep).map(|(p, _)| *p).collect()
+}
+
+fn angle_between(a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    // atan2 stays accurate for tiny angles where acos of the dot product does not
+    a.cross(b).magnitude().atan2(a.dot(b))
+}
+
+// Angle from p to the great circle arc a-b, all unit vectors
+fn angular_distance_to_arc(p: Vector3<f32>, a: Vector3<f32>, b: Vector3<f32>) -> f32 {
+    let normal = a.cross(b);
+    if normal.magnitude2() < 1e-12 {
+        // a and b (almost) coincide
+        return angle_between(p, a);
+    }
+    let normal = normal.normalize();
+    // closest point on the full great circle, only counts if it lies between a and b
+    let projected = p - normal * p.dot(normal);
+    if projected.magnitude2() > 1e-12 {
+        let projected = projected.normalize();
+        if a.cross(projected).dot(normal) >= 0.0 && projected.cross(b).dot(normal) >= 0.0 {
+            return p.dot(normal).abs().min(1.0).asin();
+        }
+    }
+    angle_between(p, a).min(angle_between(p, b))
+}
@@ -0,0 +1,183 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use cgmath::{InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+
+use crate::curves::{self, CurvePath, Frame, CURVE_RADIUS};
+
+pub const EDITED_CURVE_FILE: &str = "edited_curve.txt";
+const PICK_RADIUS_PIXELS: f32 = 12.0;
+
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    // Ray through a pixel, x and y in window coordinates with y pointing down
+    pub fn from_screen(x: f32, y: f32, width: f32, height: f32, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Option<Ray> {
+        let inverse = (projection * view).invert()?;
+        let ndc_x = 2.0 * x / width - 1.0;
+        let ndc_y = 1.0 - 2.0 * y / height;
+        let near = inverse * Vector4::new(ndc_x, ndc_y, -1.0, 1.0);
+        let far = inverse * Vector4::new(ndc_x, ndc_y, 1.0, 1.0);
+        let near = near.truncate() / near.w;
+        let far = far.truncate() / far.w;
+        Some(Ray { origin: Point3::new(near.x, near.y, near.z), direction: (far - near).normalize() })
+    }
+
+    // First hit with a sphere around the origin
+    pub fn hit_sphere(&self, radius: f32) -> Option<Vector3<f32>> {
+        let origin = Vector3::new(self.origin.x, self.origin.y, self.origin.z);
+        let b = origin.dot(self.direction);
+        let c = origin.dot(origin) - radius * radius;
+        let discriminant = b * b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let t = -b - discriminant.sqrt();
+        if t < 0.0 {
+            return None;
+        }
+        Some(origin + self.direction * t)
+    }
+}
+
+// Edit mode for authoring a curve on the globe
+// control points are (lon, lat) in degrees, the drawn curve is their bezier interpolation
+pub struct CurveEditor {
+    pub enabled: bool,
+    control_points: Vec<(f32, f32)>,
+    max_points: usize, // as many as there are markers to draw them
+    selected: Option<usize>,
+    dragging: bool,
+    dirty: bool,
+}
+
+impl CurveEditor {
+    pub fn new(max_points: usize) -> Self {
+        CurveEditor { enabled: false, control_points: Vec::new(), max_points, selected: None, dragging: false, dirty: true }
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.dragging = false;
+        println!("Curve editor {}", if self.enabled { "enabled" } else { "disabled" });
+    }
+
+    // Mouse press: grab the control point under the cursor or add a new one where the globe was clicked
+    // returns false if the click missed the globe so the camera can handle it instead
+    pub fn push(&mut self, ray: &Ray, cursor: (f32, f32), viewport: (f32, f32), view: &Matrix4<f32>, projection: &Matrix4<f32>) -> bool {
+        if let Some(index) = self.pick(cursor, viewport, ray.origin, view, projection) {
+            self.selected = Some(index);
+            self.dragging = true;
+            return true;
+        }
+        let Some(hit) = ray.hit_sphere(CURVE_RADIUS) else { return false };
+        if self.control_points.len() >= self.max_points {
+            eprintln!("The curve already has {} control points, that's the limit", self.max_points);
+            return true;
+        }
+        // new points go after the selected one so points can be inserted in the middle of the curve
+        let index = self.selected.map_or(self.control_points.len(), |selected| selected + 1);
+        self.control_points.insert(index, curves::sphere_to_lat_lon(hit));
+        self.selected = Some(index);
+        self.dragging = true;
+        self.dirty = true;
+        true
+    }
+
+    // Mouse drag: move the grabbed control point along the surface
+    pub fn drag(&mut self, ray: &Ray) -> bool {
+        if !self.dragging {
+            return false;
+        }
+        if let (Some(index), Some(hit)) = (self.selected, ray.hit_sphere(CURVE_RADIUS)) {
+            self.control_points[index] = curves::sphere_to_lat_lon(hit);
+            self.dirty = true;
+        }
+        true
+    }
+
+    pub fn release(&mut self) {
+        self.dragging = false;
+    }
+
+    pub fn delete_selected(&mut self) {
+        if let Some(index) = self.selected.take() {
+            self.control_points.remove(index);
+            // keep a selection so repeated deletes walk back along the curve
+            self.selected = index.checked_sub(1).or(if self.control_points.is_empty() { None } else { Some(0) });
+            self.dirty = true;
+        }
+    }
+
+    // True once after every change, the caller then re-uploads the curve
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    pub fn curve(&self) -> Vec<(f32, f32, f32)> {
+        curves::lat_lon_on_sphere(curves::bezier_curve_interpolation(self.control_points.clone()))
+    }
+
+    pub fn control_point_frames(&self) -> Vec<Frame> {
+        let points = curves::lat_lon_on_sphere(self.control_points.clone());
+        CurvePath::new(&points).frames().to_vec()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::from("# lon lat\n");
+        for (lon, lat) in &self.control_points {
+            contents.push_str(&format!("{} {}\n", lon, lat));
+        }
+        fs::write(path, contents)
+    }
+
+    pub fn load(path: &Path, max_points: usize) -> io::Result<Self> {
+        let mut editor = CurveEditor::new(max_points);
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f32> = line.split_whitespace().filter_map(|v| v.parse().ok()).collect();
+            match values[..] {
+                [lon, lat] => editor.control_points.push((lon, lat)),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line {}: expected 'lon lat'", number + 1))),
+            }
+        }
+        if editor.control_points.len() > max_points {
+            eprintln!("{} has {} control points, only the first {} are kept", path.display(), editor.control_points.len(), max_points);
+            editor.control_points.truncate(max_points);
+        }
+        Ok(editor)
+    }
+
+    // Closest control point within the pick radius that is on the visible side of the globe
+    fn pick(&self, cursor: (f32, f32), viewport: (f32, f32), eye: Point3<f32>, view: &Matrix4<f32>, projection: &Matrix4<f32>) -> Option<usize> {
+        let view_projection = projection * view;
+        let eye = Vector3::new(eye.x, eye.y, eye.z);
+        let mut closest = None;
+        let mut closest_distance = PICK_RADIUS_PIXELS;
+        for (i, (x, y, z)) in curves::lat_lon_on_sphere(self.control_points.clone()).into_iter().enumerate() {
+            let point = Vector3::new(x, y, z);
+            if (eye - point).dot(point) <= 0.0 {
+                continue; // behind the globe
+            }
+            let clip = view_projection * point.extend(1.0);
+            if clip.w <= 0.0 {
+                continue;
+            }
+            let screen_x = (clip.x / clip.w + 1.0) / 2.0 * viewport.0;
+            let screen_y = (1.0 - clip.y / clip.w) / 2.0 * viewport.1;
+            let distance = ((screen_x - cursor.0).powi(2) + (screen_y - cursor.1).powi(2)).sqrt();
+            if distance < closest_distance {
+                closest_distance = distance;
+                closest = Some(i);
+            }
+        }
+        closest
+    }
+}
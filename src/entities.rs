@@ -0,0 +1,160 @@
+use std::ptr;
+
+use cgmath::Matrix4;
+use gl::types::{GLenum, GLfloat, GLsizei, GLsizeiptr, GLuint};
+
+use crate::curves::{CurvePath, Frame};
+use crate::hexgrid::HexGrid;
+use crate::shader_interface::{self, VertexAttribute};
+use crate::shader_reload::HotProgram;
+use crate::shader_utils::{ProgramBuilder, ShaderError};
+
+// Something moving along one of the curves, e.g. a vehicle on a road
+pub struct Traveller {
+    pub curve: usize,  // index into the list of curve paths
+    pub distance: f32, // arc length travelled along the curve
+    pub speed: f32,    // world units per second on a tile with the default movement cost
+}
+
+impl Traveller {
+    pub fn new(curve: usize, speed: f32) -> Self {
+        Traveller { curve, distance: 0.0, speed }
+    }
+
+    // Move by the frame delta and return the new frame, loops back to the start at the end of the curve
+    // the tile it's on sets the pace, e.g. travellers speed up on roads
+    pub fn advance(&mut self, path: &CurvePath, grid: &HexGrid, delta_seconds: f32) -> Frame {
+        let length = path.length();
+        let cost = grid.movement_cost_at(path.frame_at(self.distance).position);
+        self.distance += self.speed / cost * delta_seconds;
+        if length > 0.0 {
+            self.distance = self.distance.rem_euclid(length);
+        }
+        path.frame_at(self.distance)
+    }
+}
+
+// Small arrow head pointing along x with its top along y
+const MARKER_MESH: [[f32; 3]; 12] = [
+    [0.06, 0.0, 0.0], [-0.03, 0.0, 0.025], [-0.03, 0.02, 0.0],   // right side
+    [0.06, 0.0, 0.0], [-0.03, 0.02, 0.0], [-0.03, 0.0, -0.025],  // left side
+    [0.06, 0.0, 0.0], [-0.03, 0.0, -0.025], [-0.03, 0.0, 0.025], // bottom
+    [-0.03, 0.0, 0.025], [-0.03, 0.0, -0.025], [-0.03, 0.02, 0.0], // back
+];
+const FLOATS_PER_INSTANCE: usize = 12; // position, tangent, normal, binormal
+
+const MESH_LAYOUT: [VertexAttribute; 1] = [VertexAttribute { location: 0, name: "mesh position", components: 3 }];
+const INSTANCE_LAYOUT: [VertexAttribute; 4] = [
+    VertexAttribute { location: 1, name: "frame position", components: 3 },
+    VertexAttribute { location: 2, name: "frame tangent", components: 3 },
+    VertexAttribute { location: 3, name: "frame normal", components: 3 },
+    VertexAttribute { location: 4, name: "frame binormal", components: 3 },
+];
+const MARKER_UNIFORMS: [(&str, GLenum); 4] = [
+    ("model", gl::FLOAT_MAT4), ("view", gl::FLOAT_MAT4), ("projection", gl::FLOAT_MAT4), ("color", gl::FLOAT_VEC3),
+];
+
+// Draws one instance of the marker mesh per frame
+pub struct MarkerRenderer {
+    program: HotProgram,
+    vao: GLuint,
+    vbo_mesh: GLuint,
+    vbo_instances: GLuint,
+    capacity: usize,
+    count: usize,
+    color: [f32; 3],
+}
+
+impl MarkerRenderer {
+    pub fn new(capacity: usize, color: [f32; 3]) -> Result<Self, ShaderError> {
+        let program = HotProgram::new(marker_shaders())?;
+
+        let mut vao = 0;
+        let mut vbo_mesh = 0;
+        let mut vbo_instances = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::BindVertexArray(vao);
+
+            // MESH
+            gl::GenBuffers(1, &mut vbo_mesh);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_mesh);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           std::mem::size_of_val(&MARKER_MESH) as GLsizeiptr,
+                           MARKER_MESH.as_ptr() as *const _,
+                           gl::STATIC_DRAW);
+            shader_interface::set_vertex_layout(&MESH_LAYOUT, 0);
+
+            // INSTANCES, rewritten every frame
+            gl::GenBuffers(1, &mut vbo_instances);
+            gl::BindBuffer(gl::ARRAY_BUFFER, vbo_instances);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (capacity * FLOATS_PER_INSTANCE * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           ptr::null(),
+                           gl::DYNAMIC_DRAW);
+            shader_interface::set_vertex_layout(&INSTANCE_LAYOUT, 1); // advance once per marker instead of once per vertex
+
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        Ok(MarkerRenderer { program, vao, vbo_mesh, vbo_instances, capacity, count: 0, color })
+    }
+
+    pub fn upload(&mut self, frames: &[Frame]) {
+        let frames = &frames[..frames.len().min(self.capacity)];
+        let mut instance_data: Vec<f32> = Vec::with_capacity(frames.len() * FLOATS_PER_INSTANCE);
+        for frame in frames {
+            instance_data.extend_from_slice(&[frame.position.x, frame.position.y, frame.position.z]);
+            instance_data.extend_from_slice(&[frame.tangent.x, frame.tangent.y, frame.tangent.z]);
+            instance_data.extend_from_slice(&[frame.normal.x, frame.normal.y, frame.normal.z]);
+            instance_data.extend_from_slice(&[frame.binormal.x, frame.binormal.y, frame.binormal.z]);
+        }
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo_instances);
+            gl::BufferSubData(gl::ARRAY_BUFFER,
+                              0,
+                              (instance_data.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                              instance_data.as_ptr() as *const _);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.count = frames.len();
+    }
+
+    pub fn reload_shaders(&mut self) {
+        self.program.reload_if_changed();
+    }
+
+    pub fn draw(&self, view: &Matrix4<f32>, projection: &Matrix4<f32>, model: &Matrix4<f32>) {
+        self.program.use_program();
+        self.program.set_mat4("view", view);
+        self.program.set_mat4("projection", projection);
+        self.program.set_mat4("model", model);
+        self.program.set_vec3("color", self.color);
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            gl::DrawArraysInstanced(gl::TRIANGLES, 0, MARKER_MESH.len() as GLsizei, self.count as GLsizei);
+            gl::BindVertexArray(0);
+            gl::UseProgram(0);
+        }
+    }
+}
+
+pub fn marker_shaders() -> ProgramBuilder {
+    ProgramBuilder::new()
+        .vertex("markers_v.glsl")
+        .fragment("markers_f.glsl")
+        .vertex_layout(&MESH_LAYOUT)
+        .vertex_layout(&INSTANCE_LAYOUT)
+        .uniforms(&MARKER_UNIFORMS)
+}
+
+impl Drop for MarkerRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo_mesh);
+            gl::DeleteBuffers(1, &self.vbo_instances);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, Vector3};
+
+// Passed to the sphere shaders as SUBDIVISIONS
+pub const HEX_SUBDIVISIONS: f32 = 10.0;
+pub const DEFAULT_MOVEMENT_COST: f32 = 1.0; // tiles nothing was set for
+const TILE_KEY_SCALE: f32 = 10000.0;
+const SAMPLE_STEP: f32 = 0.002; // radians between samples when walking a curve, well below the tile size
+
+// Hex tiles are identified by their centre on the unit sphere, snapped to fixed point
+// so tiles cut in half by a triangle edge get the same id from both triangles
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TileId(i32, i32, i32);
+
+// CPU copy of the hex grid the fragment shader draws on the sphere mesh, plus data per tile
+pub struct HexGrid {
+    triangles: Vec<[Vector3<f32>; 3]>, // corners ordered by their bary attribute
+    movement_costs: HashMap<TileId, f32>, // relative time to cross a tile
+}
+
+impl HexGrid {
+    pub fn from_mesh(vertices: &[[f32; 9]], indices: &[u16]) -> Self {
+        let mut triangles = Vec::with_capacity(indices.len() / 3);
+        for chunk in indices.chunks(3) {
+            let mut corners = [Vector3::new(0.0, 0.0, 0.0); 3];
+            for &index in chunk {
+                let v = vertices[index as usize];
+                corners[v[8] as usize % 3] = Vector3::new(v[0], v[1], v[2]);
+            }
+            triangles.push(corners);
+        }
+        HexGrid { triangles, movement_costs: HashMap::new() }
+    }
+
+    // Tile under the given point, the point is projected onto the mesh along the ray from the origin
+    pub fn tile_at(&self, point: Vector3<f32>) -> Option<TileId> {
+        self.tile_center_at(point).map(tile_id)
+    }
+
+    pub fn tile_center_at(&self, point: Vector3<f32>) -> Option<Vector3<f32>> {
+        for [a, b, c] in &self.triangles {
+            if let Some(barys) = ray_barycentrics(point, *a, *b, *c) {
+                let hex = get_hex_center(barys * HEX_SUBDIVISIONS) / HEX_SUBDIVISIONS;
+                let center = *a * hex.x + *b * hex.y + *c * hex.z;
+                return Some(center.normalize());
+            }
+        }
+        None
+    }
+
+    pub fn set_movement_cost(&mut self, tile: TileId, cost: f32) {
+        self.movement_costs.insert(tile, cost);
+    }
+
+    // Cost of the tile under the point
+    pub fn movement_cost_at(&self, point: Vector3<f32>) -> f32 {
+        self.tile_at(point).and_then(|tile| self.movement_costs.get(&tile).copied()).unwrap_or(DEFAULT_MOVEMENT_COST)
+    }
+
+    // Every tile the curve passes through in order of travel, without consecutive repeats
+    pub fn curve_tiles(&self, points: &[(f32, f32, f32)]) -> Vec<TileId> {
+        let mut tiles: Vec<TileId> = Vec::new();
+        let mut visit = |direction: Vector3<f32>| {
+            if let Some(tile) = self.tile_at(direction) {
+                if tiles.last() != Some(&tile) {
+                    tiles.push(tile);
+                }
+            }
+        };
+
+        let directions: Vec<Vector3<f32>> = points.iter().map(|&(x, y, z)| Vector3::new(x, y, z).normalize()).collect();
+        if let Some(&first) = directions.first() {
+            visit(first);
+        }
+        for segment in directions.windows(2) {
+            let (from, to) = (segment[0], segment[1]);
+            let angle = from.cross(to).magnitude().atan2(from.dot(to));
+            let steps = (angle / SAMPLE_STEP).ceil().max(1.0) as usize;
+            for step in 1..=steps {
+                visit(slerp(from, to, angle, step as f32 / steps as f32));
+            }
+        }
+        tiles
+    }
+}
+
+fn tile_id(center: Vector3<f32>) -> TileId {
+    TileId(
+        (center.x * TILE_KEY_SCALE).round() as i32,
+        (center.y * TILE_KEY_SCALE).round() as i32,
+        (center.z * TILE_KEY_SCALE).round() as i32,
+    )
+}
+
+fn slerp(from: Vector3<f32>, to: Vector3<f32>, angle: f32, t: f32) -> Vector3<f32> {
+    if angle < 1e-6 {
+        return from;
+    }
+    (from * ((1.0 - t) * angle).sin() + to * (t * angle).sin()) / angle.sin()
+}
+
+// Barycentric coordinates where the ray from the origin through `direction` hits triangle abc, if it does
+fn ray_barycentrics(direction: Vector3<f32>, a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> Option<Vector3<f32>> {
+    // Möller-Trumbore with the ray origin at the centre of the sphere
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < 1e-9 {
+        return None;
+    }
+    let s = -a;
+    let u = s.dot(p) / det;
+    let q = s.cross(edge1);
+    let v = direction.dot(q) / det;
+    let t = edge2.dot(q) / det;
+    const EPSILON: f32 = 1e-6;
+    if t <= 0.0 || u < -EPSILON || v < -EPSILON || u + v > 1.0 + EPSILON {
+        return None;
+    }
+    Some(Vector3::new(1.0 - u - v, u, v))
+}
+
+// Same as get_hex_center in fragment.glsl, barys are scaled by HEX_SUBDIVISIONS
+fn get_hex_center(barys: Vector3<f32>) -> Vector3<f32> {
+    let x = barys.x.ceil() - barys.x;
+    let y = barys.y.ceil() - barys.y;
+    let z = barys.z.ceil() - barys.z;
+    let mut hex = Vector3::new(barys.x.floor(), barys.y.floor(), barys.z.floor());
+    if x <= y && x <= z {
+        hex.x = barys.x.ceil();
+    } else if y <= z && y <= x {
+        hex.y = barys.y.ceil();
+    } else if z <= y && z <= x {
+        hex.z = barys.z.ceil();
+    }
+    let invalid_hex = hex.x + hex.y + hex.z != HEX_SUBDIVISIONS;
+    if invalid_hex {
+        if x <= y || x <= z {
+            hex.x = barys.x.ceil();
+        }
+        if y <= z || y <= x {
+            hex.y = barys.y.ceil();
+        }
+        if z <= y || z <= x {
+            hex.z = barys.z.ceil();
+        }
+    }
+    hex
+}
+
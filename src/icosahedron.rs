@@ -4,6 +4,7 @@ use cgmath::{InnerSpace, Vector3};
 use cgmath::num_traits::abs;
 
 const PHI: f32 = (1.0 + 2.23606) / 2.0; // 2.236 is sqrt(5)
+pub const SPHERE_RADIUS: f32 = 1.902; // length of (1, PHI, 0), the distance of every vertex to the centre
 
 fn get_indices() -> Vec<u16> {
     vec![
@@ -0,0 +1,104 @@
+use gl::types::{GLfloat, GLint, GLsizei, GLsizeiptr, GLuint};
+
+use crate::shader_interface::{self, VertexAttribute};
+
+// Per vertex: position (4), arc length (1), colour (4), dash length, gap length, flow speed (3), fade (1)
+pub const FLOATS_PER_LINE_VERTEX: usize = 13;
+pub const LINE_VERTEX_LAYOUT: [VertexAttribute; 5] = [
+    VertexAttribute { location: 0, name: "position", components: 4 },
+    VertexAttribute { location: 1, name: "arc length", components: 1 },
+    VertexAttribute { location: 2, name: "colour", components: 4 },
+    VertexAttribute { location: 3, name: "dash style", components: 3 },
+    VertexAttribute { location: 4, name: "fade", components: 1 },
+];
+
+// How a curve is drawn, lengths are world distances along the curve
+#[derive(Clone, Copy, Debug)]
+pub struct LineStyle {
+    pub color: [f32; 4],
+    pub dash_length: f32, // 0 for a solid line
+    pub gap_length: f32,
+    pub flow_speed: f32,  // world units per second the dashes move along the curve
+    pub fade: bool,       // fade out with view distance, off for curves that are meant to be seen from afar
+}
+
+impl LineStyle {
+    pub fn solid(color: [f32; 4]) -> Self {
+        LineStyle { color, dash_length: 0.0, gap_length: 0.0, flow_speed: 0.0, fade: true }
+    }
+
+    pub fn dashed(color: [f32; 4], dash_length: f32, gap_length: f32) -> Self {
+        LineStyle { color, dash_length, gap_length, flow_speed: 0.0, fade: true }
+    }
+
+    pub fn flowing(color: [f32; 4], dash_length: f32, gap_length: f32, flow_speed: f32) -> Self {
+        LineStyle { color, dash_length, gap_length, flow_speed, fade: true }
+    }
+
+    pub fn without_fade(self) -> Self {
+        LineStyle { fade: false, ..self }
+    }
+}
+
+// Vertex data for all curves in one buffer plus the first vertex and vertex count of each line strip
+pub fn build_line_vertices(curves: &[(Vec<(f32, f32, f32)>, LineStyle)]) -> (Vec<f32>, Vec<GLint>, Vec<GLsizei>) {
+    let mut float_vec = Vec::new();
+    let mut firsts = Vec::with_capacity(curves.len());
+    let mut counts = Vec::with_capacity(curves.len());
+
+    for (points, style) in curves {
+        firsts.push((float_vec.len() / FLOATS_PER_LINE_VERTEX) as GLint);
+        counts.push(points.len() as GLsizei);
+
+        let mut arc_length = 0.0;
+        let mut previous = None;
+        for &(x, y, z) in points {
+            if let Some((px, py, pz)) = previous {
+                let (dx, dy, dz): (f32, f32, f32) = (x - px, y - py, z - pz);
+                arc_length += (dx * dx + dy * dy + dz * dz).sqrt();
+            }
+            previous = Some((x, y, z));
+
+            float_vec.extend_from_slice(&[x, y, z, 1.0, arc_length]);
+            float_vec.extend_from_slice(&style.color);
+            float_vec.extend_from_slice(&[style.dash_length, style.gap_length, style.flow_speed]);
+            float_vec.push(if style.fade { 1.0 } else { 0.0 });
+        }
+    }
+
+    (float_vec, firsts, counts)
+}
+
+// Attribute layout for FLOATS_PER_LINE_VERTEX, the lines VAO and VBO must be bound
+pub unsafe fn setup_line_attributes() {
+    shader_interface::set_vertex_layout(&LINE_VERTEX_LAYOUT, 0);
+}
+
+// The lines VBO together with the line strips currently stored in it
+pub struct LineBuffer {
+    vbo: GLuint,
+    pub firsts: Vec<GLint>,
+    pub counts: Vec<GLsizei>,
+}
+
+impl LineBuffer {
+    // The VBO must already be attached to the lines VAO
+    pub fn new(vbo: GLuint) -> Self {
+        LineBuffer { vbo, firsts: Vec::new(), counts: Vec::new() }
+    }
+
+    // Replace the whole buffer, cheap enough to do whenever a curve changes
+    pub fn upload(&mut self, curves: &[(Vec<(f32, f32, f32)>, LineStyle)]) {
+        let (float_vec, firsts, counts) = build_line_vertices(curves);
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER,
+                           (float_vec.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           float_vec.as_ptr() as *const _,
+                           gl::DYNAMIC_DRAW);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        self.firsts = firsts;
+        self.counts = counts;
+    }
+}
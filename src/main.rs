@@ -2,49 +2,141 @@
 
 use std::cell::RefCell;
 use std::collections::{HashMap, VecDeque};
-use std::ffi::CString;
 use std::ptr;
 use std::rc::Rc;
 use std::thread::sleep_ms;
 use std::time::{Duration, Instant};
 
-use cgmath::{Deg, Matrix, Matrix4, Point3, SquareMatrix, Vector3};
+use cgmath::{Deg, Matrix4, Point3, SquareMatrix, Vector3};
 use fltk::{app, image::IcoImage, prelude::*, window::GlWindow};
 use fltk::app::{event_button, event_dy, event_x, event_y, MouseButton, MouseWheel, sleep};
 use fltk::enums::{Event, Key};
-use gl::types::{GLchar, GLfloat, GLint, GLsizei, GLsizeiptr, GLuint, GLuint64};
-use rand::Rng;
+use gl::types::{GLchar, GLenum, GLfloat, GLint, GLsizei, GLsizeiptr, GLuint, GLuint64};
 
 mod icosahedron;
 mod shader_utils;
+mod shader_reload;
+mod shader_program;
+mod shader_interface;
+mod shader_check;
 mod texture;
 mod curves;
+mod editor;
+mod entities;
+mod hexgrid;
+mod lines;
+mod particles;
+mod particles_cpu;
+mod satellites;
+mod trails;
+mod wind;
+mod assets;
+
+use shader_interface::VertexAttribute;
 
 const W: i32 = 1200;
 const H: i32 = 800;
 
+const CURVE_SEED: u64 = 42;
+const NUM_RANDOM_CURVES: usize = 32; // raise to a few hundred to stress-test the lines pipeline
+const CURVE_SIMPLIFY_TOLERANCE: f32 = 0.0005; // radians, about 3km on earth
+const ROAD_MOVEMENT_COST: f32 = 0.5;
+const TRAVELLER_SPEED: f32 = 0.3; // world units per second along the curves
+const LINE_FADE_START: f32 = 4.0; // view distance where lines start fading, about the sphere's horizon
+const LINE_FADE_END: f32 = 7.0;
+const MAX_CONTROL_POINTS: usize = 256;
+const PARTICLE_CHECK_FRAMES: usize = 300;
+const PARTICLE_CHECK_TOLERANCE: f32 = 1e-3;
+const EARTH_TEXTURE: &str = "earth.png";
+const ICON_FILE: &str = "fltk.ico";
+const SATELLITE_FILE: &str = "satellites.tle";
+const SATELLITE_TIME_SCALE: f64 = 60.0; // simulated seconds per real second
+const ORBIT_SEGMENTS: usize = 128;
+const WIND_FILE: &str = "wind.txt";
+const WIND_PARTICLES: usize = 1 << 20; // capacity, the GPU budget decides how many are simulated
+const PARTICLE_GPU_BUDGET_MS: f32 = 4.0; // for updating and drawing all particle systems together
+const PARTICLE_BUDGET_INTERVAL: usize = 10; // frames between count adjustments, same as the timing average
+
+// 9 floats per sphere vertex, see icosahedron.rs
+const SPHERE_LAYOUT: [VertexAttribute; 4] = [
+    VertexAttribute { location: 0, name: "position", components: 3 },
+    VertexAttribute { location: 1, name: "normal", components: 3 },
+    VertexAttribute { location: 2, name: "uv", components: 2 },
+    VertexAttribute { location: 3, name: "bary", components: 1 },
+];
+const SPHERE_UNIFORMS: [(&str, GLenum); 13] = [
+    ("model", gl::FLOAT_MAT4), ("view", gl::FLOAT_MAT4), ("projection", gl::FLOAT_MAT4), ("viewPos", gl::FLOAT_VEC3),
+    ("textureSampler", gl::SAMPLER_2D),
+    ("light.position", gl::FLOAT_VEC3), ("light.ambient", gl::FLOAT_VEC3), ("light.diffuse", gl::FLOAT_VEC3), ("light.specular", gl::FLOAT_VEC3),
+    ("material.ambient", gl::FLOAT_VEC3), ("material.diffuse", gl::FLOAT_VEC3), ("material.specular", gl::FLOAT_VEC3), ("material.shininess", gl::FLOAT),
+];
+const LINE_UNIFORMS: [(&str, GLenum); 7] = [
+    ("model", gl::FLOAT_MAT4), ("view", gl::FLOAT_MAT4), ("projection", gl::FLOAT_MAT4), ("viewPos", gl::FLOAT_VEC3),
+    ("time", gl::FLOAT), ("fadeStart", gl::FLOAT), ("fadeEnd", gl::FLOAT),
+];
+
 fn main() {
+    if std::env::args().any(|arg| arg == "--check-shaders") {
+        // before the window is created, this has to work without a display
+        let programs = [
+            sphere_shaders(),
+            lines_shaders(),
+            entities::marker_shaders(),
+            particles::update_shaders(),
+            particles::render_shaders(),
+            trails::trail_shaders(),
+        ];
+        match shader_check::check_all(&programs) {
+            Ok(0) => return,
+            Ok(failed) => {
+                eprintln!("{} of {} programs failed", failed, programs.len());
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Shader check failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--check-particles") {
+        // compare the GPU simulation against the CPU reference, headless too so it runs on llvmpipe without a display
+        match shader_check::check_particles(&particle_config(), PARTICLE_CHECK_FRAMES, 1.0 / 60.0, PARTICLE_CHECK_TOLERANCE) {
+            Ok(max_difference) => println!("Particle check passed, largest difference {}", max_difference),
+            Err(e) => {
+                eprintln!("Particle check failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let assets = assets::Assets::from_env();
+
     let app = app::App::default();
     let mut wind = GlWindow::new(100, 100, W, H, "Smooth Camera Control Example");
-    let icon: IcoImage = IcoImage::load(std::path::Path::new("fltk.ico")).unwrap();
+    match assets.read(ICON_FILE).map_err(|e| e.to_string()).and_then(|data| IcoImage::from_data(&data).map_err(|e| e.to_string())) {
+        Ok(icon) => wind.set_icon(Some(icon)),
+        Err(e) => eprintln!("Could not load the window icon {}: {}", ICON_FILE, e),
+    }
     wind.make_resizable(true);
-    wind.set_icon(Some(icon));
     wind.end();
     wind.show();
 
     wind.make_current(); // This ensures the OpenGL context is current on this thread
     gl::load_with(|s| wind.get_proc_address(s) as *const _); // This is where you load the OpenGL functions
 
-    // region: -- sphere
-    let vertex_shader = include_str!("../shaders/vertex.glsl");
-    let fragment_shader = include_str!("../shaders/fragment.glsl");
+    if std::env::args().any(|arg| arg == "--hot-reload") {
+        shader_reload::enable();
+    }
 
-    let vertex_shader = shader_utils::compile_shader(vertex_shader, gl::VERTEX_SHADER);
-    let fragment_shader = shader_utils::compile_shader(fragment_shader, gl::FRAGMENT_SHADER);
-    let shader_program = shader_utils::link_program(vertex_shader, fragment_shader);
+    // region: -- sphere
+    let mut sphere_program = require(shader_reload::HotProgram::new(sphere_shaders()));
+    let mut earth_texture = assets.texture(EARTH_TEXTURE); // started early, it decodes while the rest is set up
 
     // Setup vertex data and buffers and configure vertex attributes
     let (raw_vertices, indices) = icosahedron::get_vertices();
+    let mut hex_grid = hexgrid::HexGrid::from_mesh(&raw_vertices, &indices);
     let vertices: Vec<f32> = raw_vertices.into_iter().flatten().collect();
     let mut vbo = 0;
     let mut vao = 0;
@@ -63,20 +155,8 @@ fn main() {
             vertices.as_ptr() as *const _,
             gl::STATIC_DRAW,
         );
-        // add to gl
-        let stride = 9 * std::mem::size_of::<GLfloat>() as GLsizei; // 9 floats per vertex
-        // add x, y, z at location 0
-        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
-        gl::EnableVertexAttribArray(0);
-        // add normals at location 1
-        gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, (3 * std::mem::size_of::<GLfloat>()) as *const _);
-        gl::EnableVertexAttribArray(1);
-        // add uv at location 2
-        gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, stride, (6 * std::mem::size_of::<GLfloat>()) as *const _);
-        gl::EnableVertexAttribArray(2);
-        // add bary at location 3
-        gl::VertexAttribPointer(3, 1, gl::FLOAT, gl::FALSE, stride, (8 * std::mem::size_of::<GLfloat>()) as *const _);
-        gl::EnableVertexAttribArray(3);
+        // add x, y, z, normals, uv and bary to gl
+        shader_interface::set_vertex_layout(&SPHERE_LAYOUT, 0);
 
         // INDICES
         gl::GenBuffers(1, &mut ebo);
@@ -90,92 +170,82 @@ fn main() {
         gl::BindBuffer(gl::ARRAY_BUFFER, 0);
         gl::BindVertexArray(0);
 
-        gl::UseProgram(shader_program);
-
         // Add texture
-        // this takes rather long
-        let texture_id = texture::create_texture("C:/Users/dries/dev/fltk-opengl-test/target/debug/earth.png");
+        // decoding takes rather long, it happens in the background and the sphere is grey until a low-res preview is ready
         gl::ActiveTexture(gl::TEXTURE0); // Activate the first texture unit
-        gl::BindTexture(gl::TEXTURE_2D, texture_id);
-        let texture_location = gl::GetUniformLocation(shader_program, CString::new("textureSampler").unwrap().as_ptr());
-        gl::Uniform1i(texture_location, 0);
-
-        // lighting
-        let light_pos = gl::GetUniformLocation(shader_program, CString::new("light.position").unwrap().as_ptr());
-        let light_ambient = gl::GetUniformLocation(shader_program, CString::new("light.ambient").unwrap().as_ptr());
-        let light_diffuse = gl::GetUniformLocation(shader_program, CString::new("light.diffuse").unwrap().as_ptr());
-        let light_specular = gl::GetUniformLocation(shader_program, CString::new("light.specular").unwrap().as_ptr());
-
-        // Assuming you have set Vec3 struct to handle data
-        gl::Uniform3fv(light_pos, 1, [-500.0, 500.0, -500.0].as_ptr()); // Example light position
-        gl::Uniform3fv(light_ambient, 1, [0.5, 0.5, 0.5].as_ptr()); // Low intensity ambient light ~ 0.5 makes sense
-        gl::Uniform3fv(light_diffuse, 1, [1.2, 1.2, 1.2].as_ptr()); // Medium intensity diffuse light ~1.25 makes sense
-        gl::Uniform3fv(light_specular, 1, [0.95, 0.95, 0.95].as_ptr()); // Strong specular light ~0.75 makes sense
-
-        // Set material properties
-        let material_ambient = gl::GetUniformLocation(shader_program, CString::new("material.ambient").unwrap().as_ptr());
-        let material_diffuse = gl::GetUniformLocation(shader_program, CString::new("material.diffuse").unwrap().as_ptr());
-        let material_specular = gl::GetUniformLocation(shader_program, CString::new("material.specular").unwrap().as_ptr());
-        let material_shininess = gl::GetUniformLocation(shader_program, CString::new("material.shininess").unwrap().as_ptr());
-
-        gl::Uniform3fv(material_ambient, 1, [0.4, 0.4, 0.4].as_ptr());
-        gl::Uniform3fv(material_diffuse, 1, [0.75, 0.75, 0.75].as_ptr());
-        gl::Uniform3fv(material_specular, 1, [0.3, 0.3, 0.3].as_ptr());
-        gl::Uniform1f(material_shininess, 32.0); // Shininess factor
-
-        // cleanup
-        gl::UseProgram(0);
+        gl::BindTexture(gl::TEXTURE_2D, earth_texture.id());
+
+        set_sphere_uniforms(&sphere_program);
     }
     // endregion: -- sphere
 
     // region: -- particles
-    let num_particles = 256;
-    let mut vao_particles = 1;
-    let mut vbo_particles = 1;
-
-    let vertex_shader_particles = include_str!("../shaders/particles_v.glsl");
-    let fragment_shader_particles = include_str!("../shaders/particles_f.glsl");
+    // the shaders need GL 3.3 where transform feedback is core anyway, this is for drivers where it's broken or slow
+    let cpu_particles = std::env::args().any(|arg| arg == "--cpu-particles");
+    let particle_config = particles::ParticleConfig { cpu_fallback: cpu_particles, ..particle_config() };
+    let mut particle_systems = vec![require(particles::ParticleSystem::new(&particle_config))];
+    // endregion: -- particles
 
-    let vertex_shader = shader_utils::compile_shader(vertex_shader_particles, gl::VERTEX_SHADER);
-    let fragment_shader = shader_utils::compile_shader(fragment_shader_particles, gl::FRAGMENT_SHADER);
-    let particles_program = shader_utils::link_particles_program(vertex_shader, fragment_shader);
-    unsafe {
-        gl::GenVertexArrays(1, &mut vao_particles);
-        gl::BindVertexArray(vao_particles);
-
-        gl::GenBuffers(1, &mut vbo_particles);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_particles);
-        gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, vbo_particles);
-
-        // Assuming each particle has a position and velocity (each 4 floats)
-        let mut particles: Vec<f32> = Vec::with_capacity(num_particles * 8);
-        for _ in 0..num_particles {
-            particles.extend_from_slice(&[rand::thread_rng().gen_range(1.0..5.0), rand::thread_rng().gen_range(1.0..5.0), rand::thread_rng().gen_range(0.0..5.0), 1.0]);  // Position
-            particles.extend_from_slice(&[rand::thread_rng().gen_range(0.001..0.01), rand::thread_rng().gen_range(0.001..0.01), 0.0, 0.0]);  // Velocity
+    // region: -- wind
+    // the same particle pipeline, blown along the surface and coloured by wind speed
+    match assets.load(WIND_FILE, wind::parse_wind) {
+        Ok(wind_field) => {
+            let lifetime = 6.0;
+            let wind_config = particles::ParticleConfig {
+                count: WIND_PARTICLES,
+                gravity_strength: 0.0,
+                emitters: vec![particles::Emitter {
+                    shape: particles::EmitterShape::Surface,
+                    spawn_rate: WIND_PARTICLES as f32 / lifetime,
+                    lifetime,
+                    speed: 0.0,
+                    cone_angle: 0.0,
+                    start_color: [1.0, 1.0, 1.0, 0.8],
+                    end_color: [1.0, 1.0, 1.0, 0.0],
+                    start_size: 0.006,
+                    end_size: 0.006,
+                }],
+                wind: Some(particles::WindLayer {
+                    texture: wind_field.upload(),
+                    field: Rc::new(wind_field),
+                    speed_scale: 0.004,
+                    altitude: 0.01,
+                    slow_color: [0.3, 0.6, 1.0, 0.6],
+                    fast_color: [1.0, 1.0, 1.0, 1.0],
+                }),
+                trail_length: 8,
+                trail_width: 1.5,
+                cpu_fallback: cpu_particles,
+                ..Default::default()
+            };
+            match particles::ParticleSystem::new(&wind_config) {
+                Ok(wind_system) => particle_systems.push(wind_system),
+                Err(e) => eprintln!("{}\nRunning without the wind layer", e),
+            }
         }
-        gl::BufferData(gl::ARRAY_BUFFER,
-                       (particles.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
-                       particles.as_ptr() as *const _,
-                       gl::STATIC_DRAW);
-
-        gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, 8 * std::mem::size_of::<GLfloat>() as GLsizei, std::ptr::null());
-        gl::EnableVertexAttribArray(0);
-
-        gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 8 * std::mem::size_of::<GLfloat>() as GLsizei, (4 * std::mem::size_of::<GLfloat>()) as *const _);
-        gl::EnableVertexAttribArray(1);
+        Err(e) => eprintln!("Could not load the wind field from {}: {}", WIND_FILE, e),
     }
-    // endregion: -- particles
+    // endregion: -- wind
+
+    // region: -- satellites
+    let satellites = assets.load(SATELLITE_FILE, satellites::parse_tle).unwrap_or_else(|e| {
+        eprintln!("Could not load satellites from {}: {}", SATELLITE_FILE, e);
+        Vec::new()
+    });
+    let satellite_frames = Rc::new(RefCell::new(Vec::with_capacity(satellites.len())));
+    let satellite_frames_rc = satellite_frames.clone();
+    let mut satellite_markers = require(entities::MarkerRenderer::new(satellites.len(), [0.8, 0.4, 1.0]));
+    let mut simulation_time: f64 = 0.0; // seconds since the epoch of the element sets
+    // endregion: -- satellites
 
     // region: -- lines
     let mut vao_lines = 2;
     let mut vbo_lines = 2;
+    let mut line_buffer;
+    let mut styled_curves: Vec<(Vec<(f32, f32, f32)>, lines::LineStyle)>;
+    let curve_paths: Vec<curves::CurvePath>;
 
-    let vertex_shader_lines = include_str!("../shaders/lines_v.glsl");
-    let fragment_shader_lines = include_str!("../shaders/lines_f.glsl");
-
-    let vertex_shader = shader_utils::compile_shader(vertex_shader_lines, gl::VERTEX_SHADER);
-    let fragment_shader = shader_utils::compile_shader(fragment_shader_lines, gl::FRAGMENT_SHADER);
-    let lines_program = shader_utils::link_program(vertex_shader, fragment_shader);
+    let mut lines_program = require(shader_reload::HotProgram::new(lines_shaders()));
     unsafe {
         gl::GenVertexArrays(1, &mut vao_lines);
         gl::BindVertexArray(vao_lines);
@@ -183,30 +253,69 @@ fn main() {
         gl::GenBuffers(1, &mut vbo_lines);
         gl::BindBuffer(gl::ARRAY_BUFFER, vbo_lines);
 
-        // Assuming each particle has a position and velocity (each 4 floats)
-        let line_points_curve = curves::bezier_curve_interpolation(curves::generate_random_bezier_points());
-        let points_on_sphere = curves::points_on_sphere(line_points_curve);
-        let mut float_vec = Vec::with_capacity(points_on_sphere.len() * 4); // Pre-allocate memory for efficiency
-        for (x, y, z) in points_on_sphere {
-            // For each (x, y) tuple, append x, y, 1.0, 1.0 to the new vector
-            float_vec.extend_from_slice(&[x, y, z, 1.0]);
+        // the fixed road plus a set of seeded random curves, each curve is drawn as its own line strip
+        let line_points_curve = curves::bezier_curve_interpolation(curves::example_road_points());
+        let mut curves_on_sphere = vec![curves::points_on_sphere(line_points_curve)];
+        for curve in curves::generate_random_curves(CURVE_SEED, NUM_RANDOM_CURVES, &curves::CurveConstraints::default()) {
+            curves_on_sphere.push(curves::lat_lon_on_sphere(curves::bezier_curve_interpolation(curve)));
+        }
+        for curve in curves_on_sphere.iter_mut() {
+            *curve = curves::simplify_on_sphere(curve, CURVE_SIMPLIFY_TOLERANCE);
         }
-        gl::BufferData(gl::ARRAY_BUFFER,
-                       (float_vec.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
-                       float_vec.as_ptr() as *const _,
-                       gl::DYNAMIC_DRAW);
 
-        gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, 4 * std::mem::size_of::<GLfloat>() as GLsizei, std::ptr::null());
-        gl::EnableVertexAttribArray(0);
+        // roads make the tiles they pass through cheaper to cross
+        for tile in hex_grid.curve_tiles(&curves_on_sphere[0]) {
+            hex_grid.set_movement_cost(tile, ROAD_MOVEMENT_COST);
+        }
+        curve_paths = curves_on_sphere.iter().map(|points| curves::CurvePath::new(points)).collect();
+
+        // road with traffic flowing along it, random curves alternate between rivers and dashed routes
+        styled_curves = curves_on_sphere.into_iter().enumerate().map(|(i, points)| {
+            let style = match i {
+                0 => lines::LineStyle::flowing([1.0, 0.7, 0.2, 1.0], 0.04, 0.02, 0.1),
+                i if i % 2 == 0 => lines::LineStyle::solid([0.3, 0.6, 1.0, 0.9]),
+                _ => lines::LineStyle::flowing([1.0, 1.0, 1.0, 0.8], 0.02, 0.02, 0.05),
+            };
+            (points, style)
+        }).collect();
+        // orbits are mostly seen from further out than the fade distance, they stay visible at any zoom
+        for satellite in &satellites {
+            styled_curves.push((satellite.elements.orbit_path(ORBIT_SEGMENTS), lines::LineStyle::solid([0.8, 0.4, 1.0, 0.5]).without_fade()));
+        }
+        // the curve from the editor goes last, it is filled in on the first draw
+        styled_curves.push((Vec::new(), lines::LineStyle::flowing([0.3, 1.0, 0.4, 1.0], 0.03, 0.01, 0.08)));
 
+        lines::setup_line_attributes();
+        line_buffer = lines::LineBuffer::new(vbo_lines);
+        line_buffer.upload(&styled_curves);
     }
+    let editor_curve_index = styled_curves.len() - 1;
     // endregion: -- lines
 
-    // Clean up shaders (they're linked into the program now, so no longer needed separately)
-    unsafe {
-        gl::DeleteShader(vertex_shader);
-        gl::DeleteShader(fragment_shader);
-    }
+    // region: -- editor
+    let editor_path = assets.save_path(editor::EDITED_CURVE_FILE);
+    let curve_editor = if editor_path.exists() {
+        editor::CurveEditor::load(&editor_path, MAX_CONTROL_POINTS).unwrap_or_else(|e| {
+            eprintln!("Could not load {}: {}", editor_path.display(), e);
+            editor::CurveEditor::new(MAX_CONTROL_POINTS)
+        })
+    } else {
+        editor::CurveEditor::new(MAX_CONTROL_POINTS)
+    };
+    let curve_editor = Rc::new(RefCell::new(curve_editor));
+    let curve_editor_rc = curve_editor.clone();
+    let mut control_point_markers = require(entities::MarkerRenderer::new(MAX_CONTROL_POINTS, [0.3, 1.0, 0.4]));
+    // endregion: -- editor
+
+    // region: -- travellers
+    // one traveller per curve, each drawn as an oriented marker
+    let mut travellers: Vec<entities::Traveller> = (0..curve_paths.len())
+        .map(|i| entities::Traveller::new(i, TRAVELLER_SPEED))
+        .collect();
+    let traveller_frames = Rc::new(RefCell::new(Vec::with_capacity(travellers.len())));
+    let traveller_frames_rc = traveller_frames.clone();
+    let mut marker_renderer = require(entities::MarkerRenderer::new(travellers.len(), [1.0, 0.6, 0.1]));
+    // endregion: -- travellers
 
     // Other initializations like setting the background color, enabling depth test etc.
     unsafe {
@@ -221,12 +330,14 @@ fn main() {
     // CAMERA
     let camera_zoom = Rc::new(RefCell::new(5.0 as f32)); // Initial zoom distance
     let camera_zoom_rc = camera_zoom.clone();
+    let camera_zoom_rc_2 = camera_zoom.clone();
     let camera_zoom_target = Rc::new(RefCell::new(5.0 as f32));
     let camera_zoom_target_rc = camera_zoom_target.clone();
 
     let camera_coordinates = Rc::new(RefCell::new((0.0 as f32, 0.0 as f32))); // horizontal, vertical angles
     let camera_coordinates_rc = camera_coordinates.clone();
     let camera_coordinates_rc_2 = camera_coordinates.clone();
+    let camera_coordinates_rc_3 = camera_coordinates.clone();
 
     let mouse_position = Rc::new(RefCell::new((0, 0)));
     let mouse_position_rc = mouse_position.clone();
@@ -269,19 +380,75 @@ fn main() {
     };
 
     // window draw call
+    let start_time = Instant::now();
+    let frame_delta = Rc::new(RefCell::new(0.0 as f32)); // seconds, measured by the main loop
+    let frame_delta_rc = frame_delta.clone();
+    let mut frames_since_budget = 0;
     wind.draw(move |_| {
-        shader_timings = draw(&shader_program, &particles_program, &lines_program, vao, vao_particles, vao_lines, &vertices, &indices, &camera_coordinates_rc.borrow(), *camera_zoom_rc.borrow());
+        // the preview once it is decoded, then a slice of the full texture per frame until it replaces the preview
+        if earth_texture.update() {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE0);
+                gl::BindTexture(gl::TEXTURE_2D, earth_texture.id());
+            }
+        }
+        let mut editor = curve_editor.borrow_mut();
+        if editor.take_dirty() {
+            // regenerate the edited curve and re-upload all lines
+            styled_curves[editor_curve_index].0 = editor.curve();
+            line_buffer.upload(&styled_curves);
+            control_point_markers.upload(&editor.control_point_frames());
+        }
+        // dev mode only, see shader_reload
+        if sphere_program.reload_if_changed() {
+            set_sphere_uniforms(&sphere_program);
+        }
+        lines_program.reload_if_changed();
+        for particle_system in particle_systems.iter_mut() {
+            particle_system.reload_shaders();
+        }
+        for markers in [&mut marker_renderer, &mut satellite_markers, &mut control_point_markers] {
+            markers.reload_shaders();
+        }
+
+        marker_renderer.upload(&traveller_frames_rc.borrow());
+        satellite_markers.upload(&satellite_frames_rc.borrow());
+        let mut markers = vec![&marker_renderer, &satellite_markers];
+        if editor.enabled {
+            markers.push(&control_point_markers);
+        }
+        drop(editor);
+        shader_timings = draw(&sphere_program, &mut particle_systems, &lines_program, vao, vao_lines, &line_buffer.firsts, &line_buffer.counts, &vertices, &indices, &markers, &camera_coordinates_rc.borrow(), *camera_zoom_rc.borrow(), start_time.elapsed().as_secs_f32(), *frame_delta_rc.borrow());
 
         // Update the timing history and calculate the average of the last ten frames
         let average_shader_timings = update_timing_history(shader_timings.clone());
         println!("Average shader timings over the last ten frames: {:?}", average_shader_timings);
+
+        // every system times itself, each one gets what the others leave of the budget
+        frames_since_budget += 1;
+        if frames_since_budget >= PARTICLE_BUDGET_INTERVAL {
+            frames_since_budget = 0;
+            let particle_ms: Vec<f32> = particle_systems.iter().map(|s| s.average_gpu_ms()).collect();
+            let total_ms: f32 = particle_ms.iter().sum();
+            for (particle_system, ms) in particle_systems.iter_mut().zip(particle_ms) {
+                particle_system.fit_to_budget(PARTICLE_GPU_BUDGET_MS - (total_ms - ms));
+            }
+        }
     });
 
     // region: -- windowing
     let key_states = Rc::new(RefCell::new(HashMap::new()));
     let key_states_rc = key_states.clone();
 
-    wind.handle(move |_, ev| {
+    // ray through the mouse cursor for picking on the globe
+    let cursor_ray = move |window: &GlWindow| {
+        let (_, view, projection) = camera_matrices(&camera_coordinates_rc_3.borrow(), *camera_zoom_rc_2.borrow());
+        let viewport = (window.w() as f32, window.h() as f32);
+        let ray = editor::Ray::from_screen(event_x() as f32, event_y() as f32, viewport.0, viewport.1, &view, &projection);
+        (ray, viewport, view, projection)
+    };
+
+    wind.handle(move |window, ev| {
         match ev {
             Event::MouseWheel => {
                 let mut zoom_target = camera_zoom_target_rc.borrow_mut();
@@ -293,11 +460,35 @@ fn main() {
                 *zoom_target = zoom_target.max(1.01).min(10.0);
                 true
             }
+            Event::Push if event_button() == MouseButton::Left as i32 && curve_editor_rc.borrow().enabled => {
+                let (ray, viewport, view, projection) = cursor_ray(window);
+                let cursor = (event_x() as f32, event_y() as f32);
+                let picked = ray.map_or(false, |ray| curve_editor_rc.borrow_mut().push(&ray, cursor, viewport, &view, &projection));
+                if picked {
+                    window.redraw();
+                } else {
+                    // missed the globe, rotate the camera instead
+                    *mouse_position_rc.borrow_mut() = (event_x(), event_y());
+                }
+                true
+            }
+            Event::Released if event_button() == MouseButton::Left as i32 => {
+                curve_editor_rc.borrow_mut().release();
+                true
+            }
             Event::Push if event_button() == MouseButton::Left as i32 => {
                 *mouse_position_rc.borrow_mut() = (event_x(), event_y());
                 true
             }
             Event::Drag if event_button() == MouseButton::Left as i32 => {
+                if curve_editor_rc.borrow().enabled {
+                    // dragging a control point takes precedence over rotating the camera
+                    let (ray, ..) = cursor_ray(window);
+                    if ray.map_or(false, |ray| curve_editor_rc.borrow_mut().drag(&ray)) {
+                        window.redraw();
+                        return true;
+                    }
+                }
                 let (prev_x, prev_y) = *mouse_position_rc.borrow();
                 let (new_x, new_y) = (event_x(), event_y());
                 *mouse_position_rc.borrow_mut() = (new_x, new_y);
@@ -322,10 +513,27 @@ fn main() {
             }
             Event::KeyDown => {
                 let mut keys = key_states_rc.borrow_mut();
-                keys.insert(app::event_key(), true);
+                // ctrl shortcuts like ctrl+s don't steer the camera
+                if !app::is_event_ctrl() {
+                    keys.insert(app::event_key(), true);
+                }
                 if app::event_key() == Key::Escape {
                     app::quit()
                 }
+                // curve editor: e toggles edit mode, delete removes the selected point, ctrl+s saves
+                let mut editor = curve_editor_rc.borrow_mut();
+                if app::event_key() == Key::from_char('e') {
+                    editor.toggle();
+                    window.redraw();
+                } else if editor.enabled && (app::event_key() == Key::Delete || app::event_key() == Key::BackSpace) {
+                    editor.delete_selected();
+                    window.redraw();
+                } else if app::event_key() == Key::from_char('s') && app::is_event_ctrl() {
+                    match editor.save(&editor_path) {
+                        Ok(()) => println!("Saved curve to {}", editor_path.display()),
+                        Err(e) => eprintln!("Could not save curve to {}: {}", editor_path.display(), e),
+                    }
+                }
                 true
             }
             Event::KeyUp => {
@@ -341,6 +549,7 @@ fn main() {
     while app.wait() {
         let current_time = Instant::now();
         let delta_ms = current_time.duration_since(last_time);
+            *frame_delta.borrow_mut() = delta_ms.as_secs_f32();
             let keys = key_states.borrow();
             let mut coordinates = camera_coordinates.borrow_mut();
             let mut zoom = camera_zoom.borrow_mut();
@@ -365,6 +574,20 @@ fn main() {
                 *zoom += f32::min(zoom_diff * 0.05, f32::max(zoom_diff, -0.0001));
             }
 
+            {
+                let mut frames = traveller_frames.borrow_mut();
+                frames.clear();
+                for traveller in travellers.iter_mut() {
+                    frames.push(traveller.advance(&curve_paths[traveller.curve], &hex_grid, delta_ms.as_secs_f32()));
+                }
+            }
+            {
+                simulation_time += delta_ms.as_secs_f64() * SATELLITE_TIME_SCALE;
+                let mut frames = satellite_frames.borrow_mut();
+                frames.clear();
+                frames.extend(satellites.iter().map(|satellite| satellite.frame_at(simulation_time)));
+            }
+
             wind.redraw();
             sleep(0.016); // sleep 16ms for 60fps
             last_time = current_time;
@@ -375,21 +598,24 @@ fn main() {
     unsafe {
         gl::DeleteVertexArrays(1, &mut vao);
         gl::DeleteBuffers(1, &mut vbo);
-        gl::DeleteProgram(shader_program);
     }
 }
 
 fn draw(
-    shader_program: &GLuint,
-    particles_program: &GLuint,
-    lines_program: &gl::types::GLuint,
+    shader_program: &shader_program::ShaderProgram,
+    particle_systems: &mut [particles::ParticleSystem],
+    lines_program: &shader_program::ShaderProgram,
     vao: GLuint,
-    vao_particles: GLuint,
     vao_lines: GLuint,
+    line_firsts: &Vec<GLint>,
+    line_counts: &Vec<GLsizei>,
     vertices: &Vec<f32>,
     indices: &Vec<u16>,
+    marker_renderers: &[&entities::MarkerRenderer],
     sphere_rotation: &(f32, f32),
-    zoom: f32
+    zoom: f32,
+    time: f32,
+    delta_seconds: f32
 ) -> Vec<f32> {
     unsafe {
         // Clear the screen and depth buffer
@@ -398,13 +624,14 @@ fn draw(
         // Timer objects
         // TODO: time using program, setting uniforms and executing draw each
         // TODO: create method for executing the shader program + measuring it with a macro
-        let mut queries = vec![0; 4]; // Assuming 2 shaders, hence 4 queries (2 per shader)
-        gl::GenQueries(4, queries.as_mut_ptr());
+        // the particle systems time themselves, see ParticleSystem::begin_timer
+        let mut queries = vec![0; 3]; // sphere draw, sphere uniforms, lines
+        gl::GenQueries(3, queries.as_mut_ptr());
 
         gl::BeginQuery(gl::TIME_ELAPSED, queries[0]); // time the execution
 
         // Bind the shader program and VAO
-        gl::UseProgram(*shader_program);
+        shader_program.use_program();
         gl::BindVertexArray(vao);
 
         // Draw the sphere
@@ -420,26 +647,14 @@ fn draw(
 
         gl::BeginQuery(gl::TIME_ELAPSED, queries[1]); // time the execution
 
-        let camera_x = zoom * sphere_rotation.0.to_radians().cos() * sphere_rotation.1.to_radians().cos();
-        let camera_y = zoom * sphere_rotation.1.to_radians().sin();
-        let camera_z = zoom * sphere_rotation.0.to_radians().sin() * sphere_rotation.1.to_radians().cos();
-
-        let eye = Point3::new(camera_x, camera_y, camera_z); // Camera's position
-        let target = Point3::new(0.0, 0.0, 0.0); // Where the camera is looking
-        let up = Vector3::new(0.0, 1.0, 0.0); // 'Up' direction in world space
-
-        let view = Matrix4::look_at(eye, target, up);
-        let projection = cgmath::perspective(Deg(45.0), W as f32 / H as f32, 0.1, 100.0);
+        let (eye, view, projection) = camera_matrices(sphere_rotation, zoom);
+        let (camera_x, camera_y, camera_z) = (eye.x, eye.y, eye.z);
         let model = Matrix4::<f32>::identity(); // Model matrix, for example
 
-        let view_loc = gl::GetUniformLocation(*shader_program, CString::new("view").unwrap().as_ptr());
-        let proj_loc = gl::GetUniformLocation(*shader_program, CString::new("projection").unwrap().as_ptr());
-        let model_loc = gl::GetUniformLocation(*shader_program, CString::new("model").unwrap().as_ptr());
-        let view_pos_location = gl::GetUniformLocation(*shader_program, CString::new("viewPos").unwrap().as_ptr());
-        gl::UniformMatrix4fv(view_loc, 1, gl::FALSE, view.as_ptr());
-        gl::UniformMatrix4fv(proj_loc, 1, gl::FALSE, projection.as_ptr());
-        gl::UniformMatrix4fv(model_loc, 1, gl::FALSE, model.as_ptr());
-        gl::Uniform3fv(view_pos_location, 1, [camera_x, camera_y, camera_z].as_ptr());
+        shader_program.set_mat4("view", &view);
+        shader_program.set_mat4("projection", &projection);
+        shader_program.set_mat4("model", &model);
+        shader_program.set_vec3("viewPos", [camera_x, camera_y, camera_z]);
 
         gl::EndQuery(gl::TIME_ELAPSED); // end the timer
 
@@ -447,57 +662,45 @@ fn draw(
         // gl::BindVertexArray(0);
         // gl::UseProgram(0);
 
-        gl::BeginQuery(gl::TIME_ELAPSED, queries[2]); // time the execution
         // PARTICLES
-        // Bind the shader program and VAO
-        gl::UseProgram(*particles_program);
-        gl::BindVertexArray(vao_particles);
-
-
-        // Enable Transform Feedback and disable rasterization to update particles
-        gl::Enable(gl::RASTERIZER_DISCARD); // Disable rasterization during feedback
-        gl::BeginTransformFeedback(gl::POINTS);
-        gl::DrawArrays(gl::POINTS, 0, 256); // Draw particles to update their positions
-        gl::EndTransformFeedback();
-        gl::Disable(gl::RASTERIZER_DISCARD); // Re-enable rasterization
-
-        // Optionally: Draw the updated particles
-        // To visualize the particles, you would usually draw them again here
-        // For debugging, you can draw them without disabling rasterization to see the update immediately
-        gl::PointSize(100.0);
-        gl::DrawArrays(gl::POINTS, 0, 256); // Draw updated particles
-
-        let view_loc = gl::GetUniformLocation(*particles_program, CString::new("view").unwrap().as_ptr());
-        let proj_loc = gl::GetUniformLocation(*particles_program, CString::new("projection").unwrap().as_ptr());
-        let model_loc = gl::GetUniformLocation(*particles_program, CString::new("model").unwrap().as_ptr());
-        // let view_pos_location = gl::GetUniformLocation(*particles_program, CString::new("viewPos").unwrap().as_ptr());
-        gl::UniformMatrix4fv(view_loc, 1, gl::FALSE, view.as_ptr());
-        gl::UniformMatrix4fv(proj_loc, 1, gl::FALSE, projection.as_ptr());
-        gl::UniformMatrix4fv(model_loc, 1, gl::FALSE, model.as_ptr());
-        // gl::Uniform3fv(view_pos_location, 1, [camera_x, camera_y, camera_z].as_ptr());
+        // separate update and render passes, the update swaps the particle buffers
+        for particle_system in particle_systems.iter_mut() {
+            particle_system.begin_timer();
+            particle_system.update(delta_seconds);
+            particle_system.draw(&view, &projection, &model);
+            particle_system.end_timer();
+        }
         // END PARTICLES
-        gl::EndQuery(gl::TIME_ELAPSED); // end the timer
 
-        gl::BeginQuery(gl::TIME_ELAPSED, queries[3]); // time the execution
+        gl::BeginQuery(gl::TIME_ELAPSED, queries[2]); // time the execution
         // LINES
         // Bind the shader program and VAO
-        gl::UseProgram(*lines_program);
+        lines_program.use_program();
         gl::BindVertexArray(vao_lines);
 
+        lines_program.set_mat4("view", &view);
+        lines_program.set_mat4("projection", &projection);
+        lines_program.set_mat4("model", &model);
+        lines_program.set_vec3("viewPos", [camera_x, camera_y, camera_z]);
+        lines_program.set_f32("time", time);
+        lines_program.set_f32("fadeStart", LINE_FADE_START);
+        lines_program.set_f32("fadeEnd", LINE_FADE_END);
+
+        // lines fade out with distance, blend them without hiding each other in the depth buffer
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
         gl::LineWidth(10.0);
-        gl::DrawArrays(gl::LINE_STRIP, 0, 100); // Draw updated particles
-
-        let view_loc = gl::GetUniformLocation(*lines_program, CString::new("view").unwrap().as_ptr());
-        let proj_loc = gl::GetUniformLocation(*lines_program, CString::new("projection").unwrap().as_ptr());
-        let model_loc = gl::GetUniformLocation(*lines_program, CString::new("model").unwrap().as_ptr());
-        // let view_pos_location = gl::GetUniformLocation(*particles_program, CString::new("viewPos").unwrap().as_ptr());
-        gl::UniformMatrix4fv(view_loc, 1, gl::FALSE, view.as_ptr());
-        gl::UniformMatrix4fv(proj_loc, 1, gl::FALSE, projection.as_ptr());
-        gl::UniformMatrix4fv(model_loc, 1, gl::FALSE, model.as_ptr());
-        // gl::Uniform3fv(view_pos_location, 1, [camera_x, camera_y, camera_z].as_ptr());
+        gl::MultiDrawArrays(gl::LINE_STRIP, line_firsts.as_ptr(), line_counts.as_ptr(), line_counts.len() as GLsizei);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
         // END LINES
         gl::EndQuery(gl::TIME_ELAPSED); // end the timer
 
+        for marker_renderer in marker_renderers {
+            marker_renderer.draw(&view, &projection, &model);
+        }
+
         // Unbind the VAO and the shader program
         gl::BindVertexArray(0);
         gl::UseProgram(0);
@@ -506,10 +709,12 @@ fn draw(
         let mut timings = Vec::with_capacity(queries.len());
         for (i, query) in queries.iter().enumerate() {
             let mut time: GLint = 0;
-            let query_id = *query - 4; // -4 to get those of previous frame
+            let query_id = *query - 3; // -3 to get those of previous frame
             gl::GetQueryObjectiv(query_id, gl::QUERY_RESULT, &mut time);
             timings.push(time as f32 / 1_000_000.0);
         }
+        // third entry for the particles like before, summed over the systems
+        timings.insert(2, particle_systems.iter().map(|s| s.gpu_ms()).sum());
 
 
         check_gl_error("During draw call");
@@ -522,6 +727,106 @@ fn draw(
     }
 }
 
+// ash from a volcano plus faint activity spread over the whole surface
+fn particle_config() -> particles::ParticleConfig {
+    particles::ParticleConfig {
+        count: 512,
+        emitters: vec![
+            particles::Emitter {
+                shape: particles::EmitterShape::Point { lon: 15.0, lat: 37.7, spread: 1.0 },
+                spawn_rate: 40.0,
+                lifetime: 4.0,
+                speed: 0.3,
+                cone_angle: 20.0,
+                start_color: [1.0, 0.5, 0.1, 1.0],
+                end_color: [0.3, 0.3, 0.3, 0.0],
+                start_size: 0.02,
+                end_size: 0.06,
+            },
+            particles::Emitter {
+                shape: particles::EmitterShape::Surface,
+                spawn_rate: 60.0,
+                lifetime: 2.0,
+                speed: 0.02,
+                cone_angle: 10.0,
+                start_color: [1.0, 0.9, 0.6, 1.0],
+                end_color: [1.0, 0.9, 0.6, 0.0],
+                start_size: 0.01,
+                end_size: 0.01,
+            },
+        ],
+        collision: particles::Collision {
+            mode: particles::CollisionMode::Bounce,
+            restitution: 0.4,
+            friction: 0.2,
+            ..Default::default()
+        },
+        trail_length: 16,
+        ..Default::default()
+    }
+}
+
+// Shaders the scene can't do without, a broken one ends the program with the error report instead of a panic
+fn sphere_shaders() -> shader_utils::ProgramBuilder {
+    shader_utils::ProgramBuilder::new()
+        .vertex("vertex.glsl")
+        .fragment("fragment.glsl")
+        .define("SUBDIVISIONS", format!("{:.1}", hexgrid::HEX_SUBDIVISIONS))
+        .vertex_layout(&SPHERE_LAYOUT)
+        .uniforms(&SPHERE_UNIFORMS)
+}
+
+fn lines_shaders() -> shader_utils::ProgramBuilder {
+    shader_utils::ProgramBuilder::new()
+        .vertex("lines_v.glsl")
+        .fragment("lines_f.glsl")
+        .vertex_layout(&lines::LINE_VERTEX_LAYOUT)
+        .uniforms(&LINE_UNIFORMS)
+}
+
+fn require<T>(result: Result<T, shader_utils::ShaderError>) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    })
+}
+
+// Uniforms of the sphere program that never change, set again whenever the program is reloaded
+fn set_sphere_uniforms(program: &shader_program::ShaderProgram) {
+    program.use_program();
+    program.set_i32("textureSampler", 0);
+
+    // lighting
+    program.set_vec3("light.position", [-500.0, 500.0, -500.0]); // Example light position
+    program.set_vec3("light.ambient", [0.5, 0.5, 0.5]); // Low intensity ambient light ~ 0.5 makes sense
+    program.set_vec3("light.diffuse", [1.2, 1.2, 1.2]); // Medium intensity diffuse light ~1.25 makes sense
+    program.set_vec3("light.specular", [0.95, 0.95, 0.95]); // Strong specular light ~0.75 makes sense
+
+    // Set material properties
+    program.set_vec3("material.ambient", [0.4, 0.4, 0.4]);
+    program.set_vec3("material.diffuse", [0.75, 0.75, 0.75]);
+    program.set_vec3("material.specular", [0.3, 0.3, 0.3]);
+    program.set_f32("material.shininess", 32.0); // Shininess factor
+
+    // cleanup
+    unsafe { gl::UseProgram(0) };
+}
+
+// Camera position, view and projection for the orbit camera around the sphere
+fn camera_matrices(sphere_rotation: &(f32, f32), zoom: f32) -> (Point3<f32>, Matrix4<f32>, Matrix4<f32>) {
+    let camera_x = zoom * sphere_rotation.0.to_radians().cos() * sphere_rotation.1.to_radians().cos();
+    let camera_y = zoom * sphere_rotation.1.to_radians().sin();
+    let camera_z = zoom * sphere_rotation.0.to_radians().sin() * sphere_rotation.1.to_radians().cos();
+
+    let eye = Point3::new(camera_x, camera_y, camera_z); // Camera's position
+    let target = Point3::new(0.0, 0.0, 0.0); // Where the camera is looking
+    let up = Vector3::new(0.0, 1.0, 0.0); // 'Up' direction in world space
+
+    let view = Matrix4::look_at(eye, target, up);
+    let projection = cgmath::perspective(Deg(45.0), W as f32 / H as f32, 0.1, 100.0);
+    (eye, view, projection)
+}
+
 fn check_gl_error(operation: &str) {
     let error = unsafe { gl::GetError() };
     if error != gl::NO_ERROR {
@@ -0,0 +1,665 @@
+use std::rc::Rc;
+
+use cgmath::Matrix4;
+use gl::types::{GLenum, GLfloat, GLint, GLsizei, GLsizeiptr, GLuint, GLuint64};
+use rand::Rng;
+
+use crate::curves;
+use crate::icosahedron::SPHERE_RADIUS;
+use crate::particles_cpu::CpuParticles;
+use crate::shader_program::ShaderProgram;
+use crate::shader_interface::{self, VertexAttribute};
+use crate::shader_reload::HotProgram;
+use crate::shader_utils::{ProgramBuilder, ShaderError};
+use crate::trails::{ParticleTrails, MAX_TRAIL_PARTICLES};
+use crate::wind::WindField;
+
+pub const FLOATS_PER_PARTICLE: usize = 7; // position + age and velocity, the emitter follows from the slot
+pub const MIN_ACTIVE_PARTICLES: usize = 1024; // simulation starts here and the GPU budget never cuts it below this
+pub const MAX_EMITTERS: usize = 8; // size of the emitter uniform arrays, passed to the particle shaders as MAX_EMITTERS
+const MIN_LIFETIME: f32 = 0.01; // seconds, emitters with a shorter lifetime are clamped to it
+
+// The particle buffers, FLOATS_PER_PARTICLE floats each
+const PARTICLE_LAYOUT: [VertexAttribute; 2] = [
+    VertexAttribute { location: 0, name: "position and age", components: 4 },
+    VertexAttribute { location: 1, name: "velocity", components: 3 },
+];
+
+const UPDATE_UNIFORMS: [(&str, GLenum); 20] = [
+    ("deltaTime", gl::FLOAT), ("gravityMagnitude", gl::FLOAT), ("gravityCenter", gl::FLOAT_VEC3), ("drag", gl::FLOAT),
+    ("emitterCount", gl::INT), ("emitterEnd", gl::INT), ("emitterShape", gl::FLOAT_VEC4), ("emitterMotion", gl::FLOAT_VEC4),
+    ("surfaceRadius", gl::FLOAT), ("randomSeed", gl::UNSIGNED_INT),
+    ("collisionMode", gl::INT), ("restitution", gl::FLOAT), ("friction", gl::FLOAT),
+    ("useHeightmap", gl::BOOL), ("heightmap", gl::SAMPLER_2D), ("heightScale", gl::FLOAT),
+    ("useWind", gl::BOOL), ("wind", gl::SAMPLER_2D), ("windScale", gl::FLOAT), ("windAltitude", gl::FLOAT),
+];
+
+const RENDER_UNIFORMS: [(&str, GLenum); 7] = [
+    ("model", gl::FLOAT_MAT4), ("view", gl::FLOAT_MAT4), ("projection", gl::FLOAT_MAT4),
+    ("emitterSize", gl::FLOAT_VEC2), ("useSprite", gl::BOOL), ("sprite", gl::SAMPLER_2D), ("softness", gl::FLOAT),
+];
+
+// Set by set_emitter_render_uniforms
+pub(crate) const EMITTER_RENDER_UNIFORMS: [(&str, GLenum); 9] = [
+    ("emitterCount", gl::INT), ("emitterEnd", gl::INT), ("emitterMotion", gl::FLOAT_VEC4),
+    ("emitterStartColor", gl::FLOAT_VEC4), ("emitterEndColor", gl::FLOAT_VEC4),
+    ("colorBySpeed", gl::BOOL), ("slowColor", gl::FLOAT_VEC4), ("fastColor", gl::FLOAT_VEC4), ("maxSpeed", gl::FLOAT),
+];
+
+#[derive(Clone, Copy, Debug)]
+pub enum EmitterShape {
+    // spawns within `spread` degrees of (lon, lat)
+    Point { lon: f32, lat: f32, spread: f32 },
+    Surface,
+}
+
+// Emits particles from the planet surface, angles are in degrees
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    pub spawn_rate: f32, // particles per second
+    pub lifetime: f32,   // seconds
+    pub speed: f32,
+    pub cone_angle: f32, // spread of the initial velocity around the surface normal
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub start_size: f32, // world units, the sprites shrink with distance
+    pub end_size: f32,
+}
+
+impl Emitter {
+    // direction of the centre plus spread angle in radians, as in emitterShape
+    pub(crate) fn shape_uniform(&self) -> [f32; 4] {
+        match self.shape {
+            EmitterShape::Point { lon, lat, spread } => {
+                let direction = curves::lat_lon_to_direction(lon, lat);
+                [direction.x, direction.y, direction.z, spread.to_radians()]
+            }
+            EmitterShape::Surface => [0.0, 1.0, 0.0, std::f32::consts::PI],
+        }
+    }
+
+    pub(crate) fn motion_uniform(&self) -> [f32; 4] {
+        [self.speed, self.cone_angle.to_radians(), self.lifetime(), 0.0]
+    }
+
+    // the shaders divide by the lifetime, a zero one would make every particle NaN
+    pub fn lifetime(&self) -> f32 {
+        self.lifetime.max(MIN_LIFETIME)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollisionMode {
+    Off,
+    Bounce,
+    Stick, // particles stop where they hit the surface
+}
+
+// Collision of particles with the planet surface
+#[derive(Clone, Debug)]
+pub struct Collision {
+    pub mode: CollisionMode,
+    pub restitution: f32,         // fraction of the normal velocity kept after a bounce
+    pub friction: f32,            // fraction of the tangential velocity lost on impact
+    pub heightmap: Option<GLuint>, // texture displacing the surface, None for the plain sphere
+    pub height_scale: f32,
+}
+
+impl Default for Collision {
+    fn default() -> Self {
+        Collision { mode: CollisionMode::Off, restitution: 0.5, friction: 0.1, heightmap: None, height_scale: 0.0 }
+    }
+}
+
+impl Collision {
+    fn mode_uniform(&self) -> i32 {
+        match self.mode {
+            CollisionMode::Off => 0,
+            CollisionMode::Bounce => 1,
+            CollisionMode::Stick => 2,
+        }
+    }
+}
+
+// Particles carried along the surface by a wind field instead of falling under gravity
+// they are coloured by speed, the emitter colours only fade them in and out over their lifetime
+#[derive(Clone, Debug)]
+pub struct WindLayer {
+    pub field: Rc<WindField>, // sampled by the CPU simulation
+    pub texture: GLuint,      // the same field uploaded with WindField::upload
+    pub speed_scale: f32,     // world units per second for a wind of 1 m/s
+    pub altitude: f32,        // height above the surface the particles travel at
+    pub slow_color: [f32; 4],
+    pub fast_color: [f32; 4], // at the strongest wind in the field
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlendMode {
+    Additive, // glowing, overlapping particles brighten each other
+    Alpha,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParticleConfig {
+    pub count: usize, // buffer capacity, fewer may be simulated to stay within a GPU time budget
+    pub gravity_strength: f32,
+    pub gravity_center: [f32; 3],
+    pub drag: f32,          // fraction of velocity lost per second
+    pub max_step: f32,      // longest time step in seconds the integrator takes at once
+    pub max_substeps: usize, // limit on steps per frame, beyond it the simulation slows down instead
+    pub emitters: Vec<Emitter>,
+    pub collision: Collision,
+    pub wind: Option<WindLayer>, // replaces gravity, drag and collision when set
+    pub cpu_fallback: bool, // simulate on the CPU instead of with transform feedback, set by --cpu-particles
+    pub blend: BlendMode,
+    pub sprite: Option<GLuint>, // texture multiplied over each sprite, None for a soft round dot
+    pub softness: f32,          // fraction of the sprite radius that fades out, without a sprite texture
+    pub trail_length: usize,    // snapshots kept per particle for its trail, below 2 turns trails off
+    pub trail_interval: f32,    // seconds between snapshots
+    pub trail_width: f32,       // pixels at the head of the trail
+}
+
+impl Default for ParticleConfig {
+    fn default() -> Self {
+        ParticleConfig {
+            count: 256,
+            gravity_strength: 0.0981,
+            gravity_center: [0.0, 0.0, 0.0],
+            drag: 0.0,
+            max_step: 1.0 / 60.0,
+            max_substeps: 8,
+            emitters: Vec::new(),
+            collision: Collision::default(),
+            wind: None,
+            cpu_fallback: false,
+            blend: BlendMode::Additive,
+            sprite: None,
+            softness: 0.5,
+            trail_length: 0,
+            trail_interval: 0.05,
+            trail_width: 2.0,
+        }
+    }
+}
+
+impl ParticleConfig {
+    // the emitters that fit in the shader's uniform arrays
+    pub fn emitters(&self) -> &[Emitter] {
+        &self.emitters[..self.emitters.len().min(MAX_EMITTERS)]
+    }
+}
+
+// Particles simulated on the GPU with transform feedback
+// the state lives in two buffers, each frame reads one and writes the other, then they swap
+pub struct ParticleSystem {
+    update_program: HotProgram,
+    render_program: HotProgram,
+    vaos: [GLuint; 2],        // one vertex per particle, for the update pass
+    render_vaos: [GLuint; 2], // the same buffers as per-instance attributes, for the quads
+    vbos: [GLuint; 2],
+    current: usize, // buffer holding the latest state
+    capacity: usize,
+    active: usize,            // slots simulated and drawn, the rest keep their old state
+    emitter_ends: Vec<i32>,   // one past the last slot of each emitter among the active ones
+    steps: u32,     // seeds the respawn randomness
+    cpu: Option<CpuParticles>, // set when the simulation runs on the CPU instead
+    trails: Option<ParticleTrails>,
+    timer_queries: [GLuint; 2], // TIME_ELAPSED around update and draw, alternating so the last frame's result can be read
+    timed_frames: usize,
+    timed_ms: f32,       // GPU time summed since the last budget adjustment
+    timed_samples: usize,
+    last_ms: f32,
+    pub config: ParticleConfig,
+}
+
+impl ParticleSystem {
+    pub fn new(config: &ParticleConfig) -> Result<Self, ShaderError> {
+        let update_program = HotProgram::new(update_shaders())?;
+        let render_program = HotProgram::new(render_shaders())?;
+
+        let needed: f32 = config.emitters().iter().map(|e| (e.spawn_rate * e.lifetime()).max(0.0)).sum();
+        if needed > config.count as f32 {
+            println!("Particle emitters need {} particles but only {} are available, spawn rates are scaled down", needed.ceil(), config.count);
+        }
+
+        // every particle starts unborn with a random negative age, so spawns are spread over the first lifetime
+        let mut rng = rand::thread_rng();
+        let emitter_ends = emitter_ends(config.count, config.emitters());
+        let mut particles: Vec<f32> = Vec::with_capacity(config.count * FLOATS_PER_PARTICLE);
+        for slot in 0..config.count {
+            let age = emitter_of(&emitter_ends, slot).map_or(-1.0, |e| -rng.gen_range(0.0..config.emitters[e].lifetime()));
+            particles.extend_from_slice(&[0.0, 0.0, 0.0, age]);  // Position
+            particles.extend_from_slice(&[0.0, 0.0, 0.0]);  // Velocity
+        }
+
+        let mut vaos = [0; 2];
+        let mut render_vaos = [0; 2];
+        let mut vbos = [0; 2];
+        unsafe {
+            gl::GenVertexArrays(2, vaos.as_mut_ptr());
+            gl::GenVertexArrays(2, render_vaos.as_mut_ptr());
+            gl::GenBuffers(2, vbos.as_mut_ptr());
+            for i in 0..2 {
+                gl::BindBuffer(gl::ARRAY_BUFFER, vbos[i]);
+                // both buffers start with the same state, the second one is overwritten by the first update
+                gl::BufferData(gl::ARRAY_BUFFER,
+                               (particles.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                               particles.as_ptr() as *const _,
+                               gl::DYNAMIC_COPY);
+
+                for (vao, divisor) in [(vaos[i], 0), (render_vaos[i], 1)] {
+                    gl::BindVertexArray(vao);
+                    shader_interface::set_vertex_layout(&PARTICLE_LAYOUT, divisor);
+                }
+            }
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+
+        let cpu = if config.cpu_fallback {
+            println!("Simulating particles on the CPU");
+            Some(CpuParticles::new(particles))
+        } else {
+            None
+        };
+
+        // only the first slots get trails, a trail for each of a million particles would take hundreds of MB
+        let trails = if config.trail_length > 1 {
+            Some(ParticleTrails::new(config.count.min(MAX_TRAIL_PARTICLES), config.trail_length, config.trail_interval, config.trail_width)?)
+        } else {
+            None
+        };
+
+        let mut timer_queries = [0; 2];
+        unsafe { gl::GenQueries(2, timer_queries.as_mut_ptr()) };
+
+        // start small and let fit_to_budget grow the count, a full million would crawl until the budget catches up
+        let active = config.count.min(MIN_ACTIVE_PARTICLES);
+        Ok(ParticleSystem {
+            update_program, render_program, vaos, render_vaos, vbos, current: 0,
+            capacity: config.count, active, emitter_ends: self::emitter_ends(active, config.emitters()), steps: 0, cpu, trails,
+            timer_queries, timed_frames: 0, timed_ms: 0.0, timed_samples: 0, last_ms: 0.0, config: config.clone(),
+        })
+    }
+
+    // Particles currently simulated, at most the capacity from the config
+    pub fn count(&self) -> usize {
+        self.active
+    }
+
+    // Simulate and draw only the first `count` slots, the emitters share them out again
+    pub fn set_count(&mut self, count: usize) {
+        self.active = count.clamp(1, self.capacity);
+        self.emitter_ends = emitter_ends(self.active, self.config.emitters());
+    }
+
+    // Put update and draw between these two to measure their GPU time, TIME_ELAPSED queries can't nest
+    // the result of the previous frame is picked up here if the GPU is done with it, it never waits
+    pub fn begin_timer(&mut self) {
+        unsafe {
+            if self.timed_frames > 0 {
+                let previous = self.timer_queries[(self.timed_frames - 1) % 2];
+                let mut available: GLint = 0;
+                gl::GetQueryObjectiv(previous, gl::QUERY_RESULT_AVAILABLE, &mut available);
+                if available != 0 {
+                    let mut nanoseconds: GLuint64 = 0;
+                    gl::GetQueryObjectui64v(previous, gl::QUERY_RESULT, &mut nanoseconds);
+                    self.last_ms = nanoseconds as f32 / 1_000_000.0;
+                    self.timed_ms += self.last_ms;
+                    self.timed_samples += 1;
+                }
+            }
+            gl::BeginQuery(gl::TIME_ELAPSED, self.timer_queries[self.timed_frames % 2]);
+        }
+    }
+
+    pub fn end_timer(&mut self) {
+        unsafe { gl::EndQuery(gl::TIME_ELAPSED) };
+        self.timed_frames += 1;
+    }
+
+    // GPU time of the last measured frame
+    pub fn gpu_ms(&self) -> f32 {
+        self.last_ms
+    }
+
+    // Average GPU time since the last budget adjustment, 0 without measurements
+    pub fn average_gpu_ms(&self) -> f32 {
+        if self.timed_samples == 0 { 0.0 } else { self.timed_ms / self.timed_samples as f32 }
+    }
+
+    // Grow or shrink the particle count towards a GPU time budget for this system's passes
+    // uses the time averaged since the previous call, so call it every few frames
+    pub fn fit_to_budget(&mut self, budget_ms: f32) {
+        let measured_ms = self.average_gpu_ms();
+        self.timed_ms = 0.0;
+        self.timed_samples = 0;
+        if measured_ms <= 0.0 {
+            return; // no timing yet
+        }
+        // the time is about linear in the count, growth is capped at doubling in case it isn't
+        let factor = (budget_ms / measured_ms).clamp(0.5, 2.0);
+        if (factor - 1.0).abs() < 0.1 {
+            return; // close enough, don't chase the noise
+        }
+        let count = ((self.active as f32 * factor) as usize).clamp(self.capacity.min(MIN_ACTIVE_PARTICLES), self.capacity);
+        if count != self.active {
+            self.set_count(count);
+        }
+    }
+
+    pub fn is_cpu(&self) -> bool {
+        self.cpu.is_some()
+    }
+
+    // Pick up shader edits in dev mode, see shader_reload
+    pub fn reload_shaders(&mut self) {
+        self.update_program.reload_if_changed();
+        self.render_program.reload_if_changed();
+        if let Some(trails) = self.trails.as_mut() {
+            trails.reload_shaders();
+        }
+    }
+
+    // Copy the latest state back from the GPU, mostly for checking the simulation
+    pub fn read_back(&self) -> Vec<f32> {
+        let mut state = vec![0.0; self.capacity * FLOATS_PER_PARTICLE];
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbos[self.current]);
+            gl::GetBufferSubData(gl::ARRAY_BUFFER,
+                                 0,
+                                 (state.len() * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                                 state.as_mut_ptr() as *mut _);
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        state
+    }
+
+    // Advance the simulation by the frame time, split into sub-steps no longer than max_step
+    // so a frame time spike doesn't make the integration blow up
+    pub fn update(&mut self, delta_seconds: f32) {
+        if delta_seconds <= 0.0 {
+            return;
+        }
+        if let Some(cpu) = self.cpu.as_mut() {
+            cpu.update(&self.config, &self.emitter_ends, self.active, delta_seconds);
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.vbos[self.current]);
+                gl::BufferSubData(gl::ARRAY_BUFFER,
+                                  0,
+                                  (self.active * FLOATS_PER_PARTICLE * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                                  cpu.state.as_ptr() as *const _);
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            }
+        } else {
+            self.update_gpu(delta_seconds);
+        }
+        if let Some(trails) = self.trails.as_mut() {
+            trails.capture(self.vbos[self.current], self.active, delta_seconds);
+        }
+    }
+
+    fn update_gpu(&mut self, delta_seconds: f32) {
+        let (steps, step) = substeps(delta_seconds, self.config.max_step, self.config.max_substeps);
+        let program = &self.update_program;
+        program.use_program();
+        program.set_f32("deltaTime", step);
+        program.set_f32("gravityMagnitude", self.config.gravity_strength);
+        program.set_vec3("gravityCenter", self.config.gravity_center);
+        program.set_f32("drag", self.config.drag);
+
+        let (shapes, motions): (Vec<[f32; 4]>, Vec<[f32; 4]>) =
+            self.config.emitters().iter().map(|e| (e.shape_uniform(), e.motion_uniform())).unzip();
+        program.set_i32("emitterCount", shapes.len() as i32);
+        program.set_i32_array("emitterEnd", &self.emitter_ends);
+        program.set_vec4_array("emitterShape", &shapes);
+        program.set_vec4_array("emitterMotion", &motions);
+        program.set_f32("surfaceRadius", SPHERE_RADIUS);
+
+        let collision = &self.config.collision;
+        program.set_i32("collisionMode", collision.mode_uniform());
+        program.set_f32("restitution", collision.restitution);
+        program.set_f32("friction", collision.friction);
+        program.set_bool("useHeightmap", collision.heightmap.is_some());
+        program.set_f32("heightScale", collision.height_scale);
+        // texture unit 0 holds the sphere texture, the heightmap goes on unit 1
+        program.set_i32("heightmap", 1);
+        if let Some(heightmap) = collision.heightmap {
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE1);
+                gl::BindTexture(gl::TEXTURE_2D, heightmap);
+                gl::ActiveTexture(gl::TEXTURE0);
+            }
+        }
+
+        program.set_bool("useWind", self.config.wind.is_some());
+        // texture unit 4, after the particle sprite and the trail buffer
+        program.set_i32("wind", 4);
+        if let Some(wind) = &self.config.wind {
+            program.set_f32("windScale", wind.speed_scale);
+            program.set_f32("windAltitude", wind.altitude);
+            unsafe {
+                gl::ActiveTexture(gl::TEXTURE4);
+                gl::BindTexture(gl::TEXTURE_2D, wind.texture);
+                gl::ActiveTexture(gl::TEXTURE0);
+            }
+        }
+
+        for _ in 0..steps {
+            self.update_program.set_u32("randomSeed", self.steps);
+            self.step();
+            self.steps = self.steps.wrapping_add(1);
+        }
+    }
+
+    // Update pass: read the current buffer, capture the new state into the other one and swap
+    fn step(&mut self) {
+        let next = 1 - self.current;
+        unsafe {
+            gl::BindVertexArray(self.vaos[self.current]);
+            gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, self.vbos[next]);
+
+            gl::Enable(gl::RASTERIZER_DISCARD); // only the captured vertices matter, skip rasterization
+            gl::BeginTransformFeedback(gl::POINTS);
+            gl::DrawArrays(gl::POINTS, 0, self.active as GLsizei);
+            gl::EndTransformFeedback();
+            gl::Disable(gl::RASTERIZER_DISCARD);
+
+            gl::BindBufferBase(gl::TRANSFORM_FEEDBACK_BUFFER, 0, 0);
+            gl::BindVertexArray(0);
+        }
+        self.current = next;
+    }
+
+    // Render pass: draw the latest state
+    pub fn draw(&self, view: &Matrix4<f32>, projection: &Matrix4<f32>, model: &Matrix4<f32>) {
+        // particles are see-through, they are depth tested against the globe but don't write depth
+        unsafe {
+            gl::Enable(gl::BLEND);
+            match self.config.blend {
+                BlendMode::Additive => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE),
+                BlendMode::Alpha => gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA),
+            }
+            gl::DepthMask(gl::FALSE);
+        }
+        // trails go first so the sprites cover their heads
+        if let Some(trails) = &self.trails {
+            trails.draw(view, projection, model, &self.config, &self.emitter_ends, self.active);
+        }
+        let program = &self.render_program;
+        program.use_program();
+        program.set_mat4("view", view);
+        program.set_mat4("projection", projection);
+        program.set_mat4("model", model);
+
+        // colour and size over lifetime
+        set_emitter_render_uniforms(program, &self.config, &self.emitter_ends);
+        let sizes: Vec<[f32; 2]> = self.config.emitters().iter().map(|e| [e.start_size, e.end_size]).collect();
+        program.set_vec2_array("emitterSize", &sizes);
+
+        program.set_bool("useSprite", self.config.sprite.is_some());
+        program.set_f32("softness", self.config.softness);
+        // texture unit 2, after the sphere texture and the collision heightmap
+        program.set_i32("sprite", 2);
+        unsafe {
+            if let Some(sprite) = self.config.sprite {
+                gl::ActiveTexture(gl::TEXTURE2);
+                gl::BindTexture(gl::TEXTURE_2D, sprite);
+                gl::ActiveTexture(gl::TEXTURE0);
+            }
+
+            // a camera facing quad per particle, the corners come from gl_VertexID
+            gl::BindVertexArray(self.render_vaos[self.current]);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, self.active as GLsizei);
+            gl::BindVertexArray(0);
+            gl::DepthMask(gl::TRUE);
+            gl::Disable(gl::BLEND);
+        }
+    }
+}
+
+pub fn update_shaders() -> ProgramBuilder {
+    // no fragment stage, nothing is rasterized during the update
+    ProgramBuilder::new()
+        .vertex("particles_update_v.glsl")
+        .define("MAX_EMITTERS", MAX_EMITTERS)
+        .feedback(&["outPosition", "outVelocity"], gl::INTERLEAVED_ATTRIBS)
+        .vertex_layout(&PARTICLE_LAYOUT)
+        .uniforms(&UPDATE_UNIFORMS)
+}
+
+pub fn render_shaders() -> ProgramBuilder {
+    ProgramBuilder::new()
+        .vertex("particles_v.glsl")
+        .fragment("particles_f.glsl")
+        .define("MAX_EMITTERS", MAX_EMITTERS)
+        .vertex_layout(&PARTICLE_LAYOUT)
+        .uniforms(&RENDER_UNIFORMS)
+        .uniforms(&EMITTER_RENDER_UNIFORMS)
+}
+
+// Lifetime and colour ramp of each emitter plus the wind colours, shared by the sprite and trail shaders
+pub(crate) fn set_emitter_render_uniforms(program: &ShaderProgram, config: &ParticleConfig, emitter_ends: &[i32]) {
+    let emitters = config.emitters();
+    let motions: Vec<[f32; 4]> = emitters.iter().map(|e| e.motion_uniform()).collect();
+    let start_colors: Vec<[f32; 4]> = emitters.iter().map(|e| e.start_color).collect();
+    let end_colors: Vec<[f32; 4]> = emitters.iter().map(|e| e.end_color).collect();
+    program.set_i32("emitterCount", emitters.len() as i32);
+    program.set_i32_array("emitterEnd", emitter_ends);
+    program.set_vec4_array("emitterMotion", &motions);
+    program.set_vec4_array("emitterStartColor", &start_colors);
+    program.set_vec4_array("emitterEndColor", &end_colors);
+
+    program.set_bool("colorBySpeed", config.wind.is_some());
+    if let Some(wind) = &config.wind {
+        program.set_vec4("slowColor", wind.slow_color);
+        program.set_vec4("fastColor", wind.fast_color);
+        program.set_f32("maxSpeed", wind.field.max_speed * wind.speed_scale);
+    }
+}
+
+// Each emitter owns a run of slots, this gives the end of each run
+// an emitter needs spawn_rate * lifetime particles alive at once, all emitters are scaled down evenly if they don't fit
+// slots past the last end are spare
+pub fn emitter_ends(count: usize, emitters: &[Emitter]) -> Vec<i32> {
+    let emitters = &emitters[..emitters.len().min(MAX_EMITTERS)];
+    let needed: Vec<f32> = emitters.iter().map(|e| (e.spawn_rate * e.lifetime()).max(0.0)).collect();
+    let total: f32 = needed.iter().sum();
+    let scale = if total > count as f32 { count as f32 / total } else { 1.0 };
+
+    // an emitter that spawns at all gets at least one slot, even if its particles barely live
+    let mut end = 0;
+    emitters.iter().zip(&needed).map(|(emitter, needed)| {
+        let slots = (needed * scale).floor() as usize;
+        let slots = if emitter.spawn_rate > 0.0 { slots.max(1) } else { slots };
+        end = (end + slots).min(count);
+        end as i32
+    }).collect()
+}
+
+// Emitter owning a slot, same as emitterOf in emitters.glsl
+pub fn emitter_of(emitter_ends: &[i32], slot: usize) -> Option<usize> {
+    emitter_ends.iter().position(|&end| (slot as i32) < end)
+}
+
+// Number of sub-steps and their length for a frame, when the frame needs more than max_substeps
+// the steps are capped at max_step and the simulation falls behind real time
+pub fn substeps(delta_seconds: f32, max_step: f32, max_substeps: usize) -> (usize, f32) {
+    let steps = (delta_seconds / max_step).ceil().max(1.0) as usize;
+    if steps > max_substeps {
+        (max_substeps, max_step)
+    } else {
+        (steps, delta_seconds / steps as f32)
+    }
+}
+
+impl Drop for ParticleSystem {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(2, self.vaos.as_ptr());
+            gl::DeleteVertexArrays(2, self.render_vaos.as_ptr());
+            gl::DeleteBuffers(2, self.vbos.as_ptr());
+            gl::DeleteQueries(2, self.timer_queries.as_ptr());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_frame_is_one_step() {
+        assert_eq!(substeps(0.01, 1.0 / 60.0, 8), (1, 0.01));
+    }
+
+    #[test]
+    fn long_frame_is_split_evenly() {
+        let (steps, step) = substeps(0.04, 1.0 / 60.0, 8);
+        assert_eq!(steps, 3);
+        assert!((step * steps as f32 - 0.04).abs() < 1e-6);
+        assert!(step <= 1.0 / 60.0);
+    }
+
+    #[test]
+    fn very_long_frame_is_capped() {
+        assert_eq!(substeps(1.0, 1.0 / 60.0, 8), (8, 1.0 / 60.0));
+    }
+
+    #[test]
+    fn empty_frame_still_takes_a_step() {
+        assert_eq!(substeps(0.0, 1.0 / 60.0, 8), (1, 0.0));
+    }
+
+    fn emitter(spawn_rate: f32, lifetime: f32) -> Emitter {
+        Emitter {
+            shape: EmitterShape::Surface,
+            spawn_rate,
+            lifetime,
+            speed: 0.1,
+            cone_angle: 10.0,
+            start_color: [1.0; 4],
+            end_color: [0.0; 4],
+            start_size: 0.01,
+            end_size: 0.01,
+        }
+    }
+
+    #[test]
+    fn zero_lifetime_is_clamped() {
+        let emitter = emitter(100.0, 0.0);
+        assert_eq!(emitter.lifetime(), MIN_LIFETIME);
+        assert!(emitter.motion_uniform()[2] > 0.0);
+    }
+
+    #[test]
+    fn short_lived_emitter_still_gets_a_slot() {
+        assert_eq!(emitter_ends(100, &[emitter(10.0, 0.0), emitter(10.0, 2.0)]), vec![1, 21]);
+    }
+
+    #[test]
+    fn idle_emitter_gets_no_slots() {
+        assert_eq!(emitter_ends(100, &[emitter(0.0, 2.0)]), vec![0]);
+    }
+}
@@ -0,0 +1,274 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::icosahedron::SPHERE_RADIUS;
+use crate::particles::{self, CollisionMode, ParticleConfig, ParticleSystem, FLOATS_PER_PARTICLE};
+
+// CPU copy of the integrator in particles_update_v.glsl, step for step and with the same random numbers
+// used when transform feedback is not available and to check the GPU results
+// the heightmap of the collision settings is ignored, the CPU always collides with the plain sphere
+pub struct CpuParticles {
+    pub state: Vec<f32>, // same layout as the GPU buffers
+    steps: u32,
+}
+
+impl CpuParticles {
+    pub fn new(state: Vec<f32>) -> Self {
+        CpuParticles { state, steps: 0 }
+    }
+
+    // Only the first `active` slots are simulated, like the GPU update pass
+    pub fn update(&mut self, config: &ParticleConfig, emitter_ends: &[i32], active: usize, delta_seconds: f32) {
+        if delta_seconds <= 0.0 {
+            return;
+        }
+        let (steps, step) = particles::substeps(delta_seconds, config.max_step, config.max_substeps);
+        for _ in 0..steps {
+            for (i, particle) in self.state.chunks_mut(FLOATS_PER_PARTICLE).take(active).enumerate() {
+                step_particle(particle, i as u32, particles::emitter_of(emitter_ends, i), config, step, self.steps);
+            }
+            self.steps = self.steps.wrapping_add(1);
+        }
+    }
+}
+
+// PCG hash, same as pcg in the update shader
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn random(seed: &mut u32) -> f32 {
+    *seed = pcg(*seed);
+    *seed as f32 / 4294967295.0
+}
+
+fn random_in_cone(axis: Vector3<f32>, angle: f32, seed: &mut u32) -> Vector3<f32> {
+    let u = random(seed);
+    let cos_theta = 1.0 + (angle.cos() - 1.0) * u; // mix(1.0, cos(angle), u)
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 6.28318530718 * random(seed);
+    let helper = if axis.y.abs() < 0.99 { Vector3::unit_y() } else { Vector3::unit_x() };
+    let tangent = helper.cross(axis).normalize();
+    let bitangent = axis.cross(tangent);
+    (tangent * phi.cos() * sin_theta + bitangent * phi.sin() * sin_theta + axis * cos_theta).normalize()
+}
+
+// GLSL mod, which unlike % follows the sign of y
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+fn step_particle(particle: &mut [f32], vertex_id: u32, emitter: Option<usize>, config: &ParticleConfig, delta_time: f32, random_seed: u32) {
+    let Some(emitter) = emitter.and_then(|e| config.emitters().get(e)) else {
+        return;
+    };
+    let shape = emitter.shape_uniform();
+    let motion = emitter.motion_uniform();
+
+    let lifetime = motion[2];
+    let age = particle[3] + delta_time;
+    let born = particle[3] < 0.0 && age >= 0.0;
+    if born || age >= lifetime {
+        let mut seed = pcg(vertex_id ^ pcg(random_seed));
+        let direction = random_in_cone(Vector3::new(shape[0], shape[1], shape[2]).normalize(), shape[3], &mut seed);
+        let velocity = random_in_cone(direction, motion[1], &mut seed) * motion[0];
+        let position = direction * SPHERE_RADIUS;
+        particle[..7].copy_from_slice(&[position.x, position.y, position.z, glsl_mod(age, lifetime), velocity.x, velocity.y, velocity.z]);
+        return;
+    }
+    if age < 0.0 {
+        particle[3] = age;
+        return;
+    }
+
+    let position = Vector3::new(particle[0], particle[1], particle[2]);
+    let velocity = Vector3::new(particle[4], particle[5], particle[6]);
+    if let Some(wind) = &config.wind {
+        let normal = position.normalize();
+        let lon = normal.z.atan2(normal.x);
+        let lat = normal.y.clamp(-1.0, 1.0).asin();
+        let (u, v) = wind.field.sample(lon.to_degrees(), lat.to_degrees());
+        let east = Vector3::new(-lon.sin(), 0.0, lon.cos());
+        let north = Vector3::new(-lat.sin() * lon.cos(), lat.cos(), -lat.sin() * lon.sin());
+        let wind_velocity = (east * u + north * v) * wind.speed_scale;
+        let wind_position = (position + wind_velocity * delta_time).normalize() * (SPHERE_RADIUS + wind.altitude);
+        particle[..7].copy_from_slice(&[wind_position.x, wind_position.y, wind_position.z, age, wind_velocity.x, wind_velocity.y, wind_velocity.z]);
+        return;
+    }
+    let center = Vector3::from(config.gravity_center);
+    let to_center = center - position;
+    let gravity_direction = if to_center.magnitude() > 0.0 { to_center.normalize() } else { Vector3::new(0.0, 0.0, 0.0) };
+    let gravity = gravity_direction * config.gravity_strength;
+    let mut new_velocity = (velocity + gravity * delta_time) * (-config.drag * delta_time).exp();
+    let mut new_position = position + new_velocity * delta_time;
+
+    let collision = &config.collision;
+    if collision.mode != CollisionMode::Off && new_position.magnitude() > 0.0 {
+        let normal = new_position.normalize();
+        let ground = SPHERE_RADIUS;
+        if new_position.magnitude() < ground {
+            new_position = normal * ground;
+            let normal_speed = new_velocity.dot(normal);
+            if collision.mode == CollisionMode::Stick {
+                new_velocity = Vector3::new(0.0, 0.0, 0.0);
+            } else if normal_speed < 0.0 {
+                let normal_velocity = normal * normal_speed;
+                let tangent_velocity = new_velocity - normal_velocity;
+                new_velocity = tangent_velocity * (1.0 - collision.friction) - normal_velocity * collision.restitution;
+            }
+        }
+    }
+
+    particle[..7].copy_from_slice(&[new_position.x, new_position.y, new_position.z, age, new_velocity.x, new_velocity.y, new_velocity.z]);
+}
+
+// Run the GPU simulation and the CPU copy side by side from the same start state, they are never synced again
+// so drift that builds up over many frames counts too
+// returns the largest difference seen, or a description of the first particle that is off by more than the tolerance
+pub fn compare_with_gpu(config: &ParticleConfig, frames: usize, delta_seconds: f32, tolerance: f32) -> Result<f32, String> {
+    let mut gpu_config = config.clone();
+    gpu_config.cpu_fallback = false;
+    gpu_config.collision.heightmap = None; // not simulated on the CPU
+    let mut gpu = ParticleSystem::new(&gpu_config).map_err(|e| e.to_string())?;
+    if gpu.is_cpu() {
+        return Err("transform feedback is not available".to_string());
+    }
+    let mut cpu = CpuParticles::new(gpu.read_back());
+    let active = gpu.count();
+    let emitter_ends = particles::emitter_ends(active, gpu_config.emitters());
+
+    let mut max_difference: f32 = 0.0;
+    for frame in 0..frames {
+        gpu.update(delta_seconds);
+        cpu.update(&gpu_config, &emitter_ends, active, delta_seconds);
+        let gpu_state = gpu.read_back();
+        for (i, (gpu_particle, cpu_particle)) in gpu_state.chunks(FLOATS_PER_PARTICLE).zip(cpu.state.chunks(FLOATS_PER_PARTICLE)).enumerate() {
+            let difference = gpu_particle.iter().zip(cpu_particle).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+            if difference > tolerance || difference.is_nan() {
+                return Err(format!("frame {} particle {}: gpu {:?} cpu {:?}", frame, i, gpu_particle, cpu_particle));
+            }
+            max_difference = max_difference.max(difference);
+        }
+    }
+    Ok(max_difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particles::{Collision, Emitter, EmitterShape};
+
+    fn config(mode: CollisionMode) -> ParticleConfig {
+        ParticleConfig {
+            gravity_strength: 0.0,
+            emitters: vec![Emitter {
+                shape: EmitterShape::Point { lon: 0.0, lat: 0.0, spread: 5.0 },
+                spawn_rate: 10.0,
+                lifetime: 2.0,
+                speed: 0.5,
+                cone_angle: 10.0,
+                start_color: [1.0; 4],
+                end_color: [0.0; 4],
+                start_size: 0.01,
+                end_size: 0.01,
+            }],
+            collision: Collision { mode, restitution: 0.5, friction: 0.25, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    // just above the ground at (1, 0, 0) and heading into it
+    fn falling_particle() -> [f32; 7] {
+        [SPHERE_RADIUS + 0.001, 0.0, 0.0, 0.5, -1.0, 0.2, 0.0]
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn pcg_matches_the_shader() {
+        // same values as pcg in particles_update_v.glsl, the two have to change together
+        assert_eq!(pcg(0), 129708002);
+        assert_eq!(pcg(1), 2831084092);
+        assert_eq!(pcg(12345), 4099845390);
+    }
+
+    #[test]
+    fn random_stays_in_unit_range() {
+        let mut seed = 7;
+        for _ in 0..10000 {
+            let r = random(&mut seed);
+            assert!((0.0..=1.0).contains(&r));
+        }
+    }
+
+    #[test]
+    fn unborn_particle_only_ages() {
+        let mut particle = [1.0, 2.0, 3.0, -1.0, 4.0, 5.0, 6.0];
+        step_particle(&mut particle, 0, Some(0), &config(CollisionMode::Off), 0.25, 0);
+        assert_close(&particle, &[1.0, 2.0, 3.0, -0.75, 4.0, 5.0, 6.0]);
+    }
+
+    #[test]
+    fn particle_without_emitter_is_left_alone() {
+        let mut particle = falling_particle();
+        step_particle(&mut particle, 0, None, &config(CollisionMode::Bounce), 0.1, 0);
+        assert_eq!(particle, falling_particle());
+    }
+
+    #[test]
+    fn respawn_is_deterministic() {
+        let config = config(CollisionMode::Off);
+        let respawn = |vertex_id, seed| {
+            let mut particle = [0.0, 0.0, 0.0, 1.95, 0.0, 0.0, 0.0];
+            step_particle(&mut particle, vertex_id, Some(0), &config, 0.1, seed);
+            particle
+        };
+        let particle = respawn(3, 11);
+        assert_eq!(particle, respawn(3, 11));
+        assert_ne!(particle, respawn(4, 11));
+        assert_ne!(particle, respawn(3, 12));
+
+        // on the surface within the emitter's spread, with the age carried over past the lifetime
+        let position = Vector3::new(particle[0], particle[1], particle[2]);
+        assert!((position.magnitude() - SPHERE_RADIUS).abs() < 1e-5);
+        assert!(position.normalize().dot(Vector3::unit_x()) >= 5.0f32.to_radians().cos() - 1e-5);
+        assert!((particle[3] - 0.05).abs() < 1e-5);
+        let speed = Vector3::new(particle[4], particle[5], particle[6]).magnitude();
+        assert!((speed - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn bounce_reflects_and_damps() {
+        let mut particle = falling_particle();
+        step_particle(&mut particle, 0, Some(0), &config(CollisionMode::Bounce), 0.01, 0);
+        let position = Vector3::new(particle[0], particle[1], particle[2]);
+        assert!((position.magnitude() - SPHERE_RADIUS).abs() < 1e-5);
+        // normal part reversed and scaled by restitution, tangential part reduced by friction
+        let normal = position.normalize();
+        let before = Vector3::new(-1.0, 0.2, 0.0);
+        let normal_velocity = normal * before.dot(normal);
+        let expected = (before - normal_velocity) * 0.75 - normal_velocity * 0.5;
+        assert_close(&particle[3..], &[0.51, expected.x, expected.y, expected.z]);
+    }
+
+    #[test]
+    fn stick_stops_on_the_surface() {
+        let mut particle = falling_particle();
+        step_particle(&mut particle, 0, Some(0), &config(CollisionMode::Stick), 0.01, 0);
+        let position = Vector3::new(particle[0], particle[1], particle[2]);
+        assert!((position.magnitude() - SPHERE_RADIUS).abs() < 1e-5);
+        assert_close(&particle[3..], &[0.51, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn no_collision_falls_through() {
+        let mut particle = falling_particle();
+        step_particle(&mut particle, 0, Some(0), &config(CollisionMode::Off), 0.01, 0);
+        assert_close(&particle, &[SPHERE_RADIUS - 0.009, 0.002, 0.0, 0.51, -1.0, 0.2, 0.0]);
+    }
+}
@@ -0,0 +1,248 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::curves::Frame;
+use crate::icosahedron::SPHERE_RADIUS;
+
+const EARTH_MU: f64 = 398600.4418; // km^3/s^2
+const EARTH_RADIUS: f64 = 6371.0;  // km, mapped onto SPHERE_RADIUS
+const SECONDS_PER_DAY: f64 = 86400.0;
+
+// Classical orbital elements, distances in km and angles in degrees
+// the mean anomaly is the one at simulation time 0
+#[derive(Clone, Debug)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub raan: f64, // right ascension of the ascending node
+    pub argument_of_periapsis: f64,
+    pub mean_anomaly: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Satellite {
+    pub name: String,
+    pub elements: OrbitalElements,
+}
+
+impl OrbitalElements {
+    // radians per second
+    pub fn mean_motion(&self) -> f64 {
+        (EARTH_MU / self.semi_major_axis.powi(3)).sqrt()
+    }
+
+    // Position (km) and velocity (km/s) in the inertial frame, z towards the north pole
+    pub fn state_at(&self, time: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let mean_anomaly = self.mean_anomaly.to_radians() + self.mean_motion() * time;
+        let eccentric_anomaly = solve_kepler(mean_anomaly, self.eccentricity);
+        self.state_at_eccentric_anomaly(eccentric_anomaly)
+    }
+
+    fn state_at_eccentric_anomaly(&self, eccentric_anomaly: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let (a, e) = (self.semi_major_axis, self.eccentricity);
+        let (sin_e, cos_e) = eccentric_anomaly.sin_cos();
+        let b_factor = (1.0 - e * e).sqrt();
+        let r = a * (1.0 - e * cos_e);
+
+        // perifocal frame, x towards periapsis
+        let position = Vector3::new(a * (cos_e - e), a * b_factor * sin_e, 0.0);
+        let speed_factor = (EARTH_MU * a).sqrt() / r;
+        let velocity = Vector3::new(-speed_factor * sin_e, speed_factor * b_factor * cos_e, 0.0);
+
+        (self.perifocal_to_inertial(position), self.perifocal_to_inertial(velocity))
+    }
+
+    // R3(-raan) * R1(-inclination) * R3(-argument of periapsis)
+    fn perifocal_to_inertial(&self, v: Vector3<f64>) -> Vector3<f64> {
+        let (sin_o, cos_o) = self.raan.to_radians().sin_cos();
+        let (sin_i, cos_i) = self.inclination.to_radians().sin_cos();
+        let (sin_w, cos_w) = self.argument_of_periapsis.to_radians().sin_cos();
+        Vector3::new(
+            (cos_o * cos_w - sin_o * sin_w * cos_i) * v.x + (-cos_o * sin_w - sin_o * cos_w * cos_i) * v.y,
+            (sin_o * cos_w + cos_o * sin_w * cos_i) * v.x + (-sin_o * sin_w + cos_o * cos_w * cos_i) * v.y,
+            (sin_w * sin_i) * v.x + (cos_w * sin_i) * v.y,
+        )
+    }
+
+    // Closed orbit in world coordinates, for drawing with the lines pipeline
+    pub fn orbit_path(&self, segments: usize) -> Vec<(f32, f32, f32)> {
+        // evenly spaced in eccentric anomaly, which puts more points near periapsis than mean anomaly would
+        (0..=segments).map(|i| {
+            let eccentric_anomaly = i as f64 / segments as f64 * std::f64::consts::TAU;
+            let p = to_world(self.state_at_eccentric_anomaly(eccentric_anomaly).0);
+            (p.x, p.y, p.z)
+        }).collect()
+    }
+}
+
+impl Satellite {
+    // Marker frame, tangent along the velocity and normal pointing away from the planet
+    pub fn frame_at(&self, time: f64) -> Frame {
+        let (position, velocity) = self.elements.state_at(time);
+        let position = to_world(position);
+        let velocity = to_world(velocity);
+        let up = position.normalize();
+        let tangent = (velocity - up * velocity.dot(up)).normalize();
+        Frame { position, tangent, normal: up, binormal: tangent.cross(up) }
+    }
+}
+
+// Inertial km to world units, north goes to +y so it matches lat_lon_to_direction
+pub fn to_world(v: Vector3<f64>) -> Vector3<f32> {
+    let scale = SPHERE_RADIUS as f64 / EARTH_RADIUS;
+    Vector3::new((v.x * scale) as f32, (v.z * scale) as f32, (v.y * scale) as f32)
+}
+
+// Eccentric anomaly for the mean anomaly, Newton iteration on M = E - e sin E
+fn solve_kepler(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let mean_anomaly = mean_anomaly.rem_euclid(std::f64::consts::TAU);
+    // starting at pi converges for every eccentricity below 1
+    let mut eccentric_anomaly = if eccentricity > 0.8 { std::f64::consts::PI } else { mean_anomaly };
+    for _ in 0..30 {
+        let f = eccentric_anomaly - eccentricity * eccentric_anomaly.sin() - mean_anomaly;
+        let step = f / (1.0 - eccentricity * eccentric_anomaly.cos());
+        eccentric_anomaly -= step;
+        if step.abs() < 1e-12 {
+            break;
+        }
+    }
+    eccentric_anomaly
+}
+
+// Two or three line element sets, the optional name line goes before each pair
+// the epoch is ignored, every set starts at its own epoch at simulation time 0
+pub fn parse_tle(text: &str) -> Result<Vec<Satellite>, String> {
+    let lines: Vec<&str> = text.lines().map(|l| l.trim_end()).filter(|l| !l.trim().is_empty() && !l.starts_with('#')).collect();
+    let mut satellites = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let name = if lines[i].starts_with("1 ") { None } else { i += 1; Some(lines[i - 1].trim().to_string()) };
+        let (Some(line1), Some(line2)) = (lines.get(i), lines.get(i + 1)) else {
+            return Err(format!("incomplete element set at line {}", i + 1));
+        };
+        if !line1.starts_with("1 ") || !line2.starts_with("2 ") {
+            return Err(format!("expected TLE lines 1 and 2 at line {}", i + 1));
+        }
+        let field = |start: usize, end: usize| -> Result<f64, String> {
+            line2.get(start..end.min(line2.len()))
+                .and_then(|s| s.trim().parse().ok())
+                .ok_or_else(|| format!("bad field at columns {}-{} of '{}'", start + 1, end, line2))
+        };
+        let mean_motion = field(52, 63)? * std::f64::consts::TAU / SECONDS_PER_DAY; // revolutions per day to rad/s
+        let elements = OrbitalElements {
+            semi_major_axis: (EARTH_MU / (mean_motion * mean_motion)).cbrt(),
+            eccentricity: field(26, 33)? / 1e7, // leading decimal point is implied
+            inclination: field(8, 16)?,
+            raan: field(17, 25)?,
+            argument_of_periapsis: field(34, 42)?,
+            mean_anomaly: field(43, 51)?,
+        };
+        let name = name.unwrap_or_else(|| line1.get(2..7).unwrap_or("").trim().to_string());
+        satellites.push(Satellite { name, elements });
+        i += 2;
+    }
+    Ok(satellites)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{PI, TAU};
+
+    const ISS: &str = "ISS (ZARYA)
+1 25544U 98067A   24001.00000000  .00000000  00000-0  00000-0 0  9990
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391    00";
+
+    fn molniya() -> OrbitalElements {
+        OrbitalElements {
+            semi_major_axis: 26600.0,
+            eccentricity: 0.74,
+            inclination: 63.4,
+            raan: 200.0,
+            argument_of_periapsis: 270.0,
+            mean_anomaly: 0.0,
+        }
+    }
+
+    fn kepler_residual(mean_anomaly: f64, eccentricity: f64) -> f64 {
+        let eccentric_anomaly = solve_kepler(mean_anomaly, eccentricity);
+        (eccentric_anomaly - eccentricity * eccentric_anomaly.sin() - mean_anomaly.rem_euclid(TAU)).abs()
+    }
+
+    #[test]
+    fn circular_orbit_anomalies_are_equal() {
+        for mean_anomaly in [0.0, 0.5, PI, 4.0, TAU - 0.01] {
+            assert!((solve_kepler(mean_anomaly, 0.0) - mean_anomaly).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn kepler_converges_at_high_eccentricity() {
+        // near periapsis is where Newton's method has the most trouble
+        for mean_anomaly in [1e-4, 0.05, 0.5, PI, 5.0, TAU - 1e-4] {
+            assert!(kepler_residual(mean_anomaly, 0.74) < 1e-10, "no convergence at M = {}", mean_anomaly);
+            assert!(kepler_residual(mean_anomaly, 0.95) < 1e-10, "no convergence at M = {}", mean_anomaly);
+        }
+    }
+
+    #[test]
+    fn state_repeats_after_one_period() {
+        let elements = molniya();
+        let period = TAU / elements.mean_motion();
+        for time in [0.0, 1000.0, period / 3.0] {
+            let (p0, v0) = elements.state_at(time);
+            let (p1, v1) = elements.state_at(time + period);
+            assert!((p1 - p0).magnitude() < 1e-6 * p0.magnitude(), "position drifted at t = {}", time);
+            assert!((v1 - v0).magnitude() < 1e-6 * v0.magnitude(), "velocity drifted at t = {}", time);
+        }
+    }
+
+    #[test]
+    fn periapsis_and_apoapsis_distances() {
+        let elements = molniya();
+        let (a, e) = (elements.semi_major_axis, elements.eccentricity);
+        let period = TAU / elements.mean_motion();
+        let (periapsis, _) = elements.state_at(0.0);
+        let (apoapsis, velocity) = elements.state_at(period / 2.0);
+        assert!((periapsis.magnitude() - a * (1.0 - e)).abs() < 1e-6);
+        assert!((apoapsis.magnitude() - a * (1.0 + e)).abs() < 1e-6);
+        // vis-viva
+        let expected_speed = (EARTH_MU * (2.0 / apoapsis.magnitude() - 1.0 / a)).sqrt();
+        assert!((velocity.magnitude() - expected_speed).abs() < 1e-9);
+        // argument of periapsis 270 puts the apoapsis over the northern hemisphere
+        assert!(apoapsis.z > 0.0);
+    }
+
+    #[test]
+    fn parses_the_iss() {
+        let satellites = parse_tle(ISS).unwrap();
+        assert_eq!(satellites.len(), 1);
+        let iss = &satellites[0];
+        assert_eq!(iss.name, "ISS (ZARYA)");
+        assert_eq!(iss.elements.inclination, 51.6416);
+        assert_eq!(iss.elements.raan, 247.4627);
+        assert!((iss.elements.eccentricity - 0.0006703).abs() < 1e-12);
+        // about 400 km up
+        let altitude = iss.elements.semi_major_axis - EARTH_RADIUS;
+        assert!(altitude > 350.0 && altitude < 450.0, "altitude {}", altitude);
+    }
+
+    #[test]
+    fn parses_the_bundled_file() {
+        let satellites = parse_tle(include_str!("../satellites.tle")).unwrap();
+        assert_eq!(satellites[0].name, "ISS (ZARYA)");
+    }
+
+    #[test]
+    fn short_line_is_an_error() {
+        let truncated = "1 25544U 98067A   24001.00000000  .00000000  00000-0  00000-0 0  9990
+2 25544  51.6416 247.4627 0006703";
+        assert!(parse_tle(truncated).is_err());
+    }
+
+    #[test]
+    fn missing_second_line_is_an_error() {
+        assert!(parse_tle("ISS (ZARYA)\n1 25544U 98067A   24001.00000000  .00000000  00000-0  00000-0 0  9990").is_err());
+        assert!(parse_tle("1 25544U\n1 25544U").is_err());
+    }
+}
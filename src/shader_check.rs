@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::fs;
+
+use khronos_egl as egl;
+
+use crate::particles::ParticleConfig;
+use crate::particles_cpu;
+use crate::shader_reload::{self, SHADER_DIR};
+use crate::shader_utils::ProgramBuilder;
+
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD; // EGL_MESA_platform_surfaceless
+
+// GL 3.3 core context without a window, through EGL so it works without a display,
+// on a machine without a GPU Mesa falls back to llvmpipe
+struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_4>,
+    display: egl::Display,
+    context: egl::Context,
+    surface: egl::Surface,
+}
+
+impl HeadlessContext {
+    fn new() -> Result<Self, String> {
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_4>::load_required() }.map_err(|e| format!("can't load libEGL: {}", e))?;
+        // the surfaceless platform doesn't need X or Wayland, drivers without it get the default display
+        let surfaceless = egl.upcast::<egl::EGL1_5>()
+            .and_then(|egl| unsafe { egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE]) }.ok());
+        let display = match surfaceless {
+            Some(display) => display,
+            None => unsafe { egl.get_display(egl::DEFAULT_DISPLAY) }.ok_or("no EGL display")?,
+        };
+        egl.initialize(display).map_err(|e| format!("can't initialize EGL: {}", e))?;
+
+        let config = egl.choose_first_config(display, &[egl::SURFACE_TYPE, egl::PBUFFER_BIT, egl::RENDERABLE_TYPE, egl::OPENGL_BIT, egl::NONE])
+            .map_err(|e| e.to_string())?
+            .ok_or("no EGL config for desktop GL")?;
+        egl.bind_api(egl::OPENGL_API).map_err(|e| e.to_string())?;
+        let context = egl.create_context(display, config, None, &[
+            egl::CONTEXT_MAJOR_VERSION, 3,
+            egl::CONTEXT_MINOR_VERSION, 3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ]).map_err(|e| format!("can't create a GL 3.3 core context: {}", e))?;
+        // nothing is drawn, the pbuffer is only there for drivers that want a surface to make a context current
+        let surface = egl.create_pbuffer_surface(display, config, &[egl::WIDTH, 1, egl::HEIGHT, 1, egl::NONE]).map_err(|e| e.to_string())?;
+        egl.make_current(display, Some(surface), Some(surface), Some(context)).map_err(|e| e.to_string())?;
+
+        gl::load_with(|name| egl.get_proc_address(name).map_or(std::ptr::null(), |f| f as *const _));
+        Ok(HeadlessContext { egl, display, context, surface })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        let _ = self.egl.make_current(self.display, None, None, None);
+        let _ = self.egl.destroy_surface(self.display, self.surface);
+        let _ = self.egl.destroy_context(self.display, self.context);
+        let _ = self.egl.terminate(self.display);
+    }
+}
+
+// Build every program from the files in SHADER_DIR, the same way the app does including the interface checks
+// prints a line or a report per program and returns how many failed
+pub fn check_all(programs: &[ProgramBuilder]) -> Result<usize, String> {
+    let _context = HeadlessContext::new()?;
+    unsafe {
+        let renderer = std::ffi::CStr::from_ptr(gl::GetString(gl::RENDERER) as *const _);
+        println!("Checking shaders in {} on {}", SHADER_DIR, renderer.to_string_lossy());
+    }
+
+    let read = RefCell::new(Vec::new()); // every file some program used, includes too
+    let mut failed = 0;
+    for program in programs {
+        read.borrow_mut().extend(program.files().iter().map(|name| name.to_string())); // even if an earlier stage fails
+        // always compiled, a cached binary only proves the sources compiled on this driver before
+        let result = program.clone().cached(false).build(|name| {
+            read.borrow_mut().push(name.to_string());
+            shader_reload::disk_source(name)
+        });
+        match result {
+            Ok(id) => {
+                println!("ok      {}", program.files().join(" + "));
+                unsafe { gl::DeleteProgram(id) };
+            }
+            Err(e) => {
+                println!("FAILED  {}\n{}", program.files().join(" + "), e);
+                failed += 1;
+            }
+        }
+    }
+
+    // a shader nothing builds is never checked, most likely it was forgotten in the list
+    let read = read.into_inner();
+    let mut unused: Vec<String> = fs::read_dir(SHADER_DIR).map_err(|e| format!("can't list {}: {}", SHADER_DIR, e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".glsl") && !read.contains(name))
+        .collect();
+    unused.sort();
+    for name in unused {
+        println!("unused  {}", name);
+    }
+    Ok(failed)
+}
+
+// GPU particles against the CPU reference on the same headless context, see particles_cpu::compare_with_gpu
+pub fn check_particles(config: &ParticleConfig, frames: usize, delta_seconds: f32, tolerance: f32) -> Result<f32, String> {
+    let _context = HeadlessContext::new()?;
+    particles_cpu::compare_with_gpu(config, frames, delta_seconds, tolerance)
+}
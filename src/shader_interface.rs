@@ -0,0 +1,135 @@
+use gl::types::{GLchar, GLenum, GLint, GLsizei, GLuint};
+
+// One attribute in a vertex buffer of tightly packed floats, the attributes follow each other in the order listed
+// `name` is only for messages, the shaders are matched by location
+#[derive(Clone, Copy, Debug)]
+pub struct VertexAttribute {
+    pub location: GLuint,
+    pub name: &'static str,
+    pub components: GLint,
+}
+
+// Point the attributes at the bound ARRAY_BUFFER, the VAO must be bound too
+// divisor 0 advances per vertex, 1 per instance
+pub unsafe fn set_vertex_layout(layout: &[VertexAttribute], divisor: GLuint) {
+    let float_size = std::mem::size_of::<f32>() as GLint;
+    let stride = layout.iter().map(|a| a.components).sum::<GLint>() * float_size;
+    let mut offset = 0;
+    for attribute in layout {
+        gl::VertexAttribPointer(attribute.location, attribute.components, gl::FLOAT, gl::FALSE, stride as GLsizei, (offset * float_size) as usize as *const _);
+        gl::EnableVertexAttribArray(attribute.location);
+        gl::VertexAttribDivisor(attribute.location, divisor);
+        offset += attribute.components;
+    }
+}
+
+// An active attribute or uniform as reported by the driver after linking
+#[derive(Clone, Debug)]
+pub struct ActiveVariable {
+    pub name: String, // arrays without the [0]
+    pub location: GLint,
+    pub kind: GLenum, // gl::FLOAT_VEC3, gl::SAMPLER_2D, ...
+    pub size: GLint,  // array length, 1 for everything else
+}
+
+pub fn active_attributes(program: GLuint) -> Vec<ActiveVariable> {
+    unsafe {
+        active_variables(program, gl::ACTIVE_ATTRIBUTES, gl::ACTIVE_ATTRIBUTE_MAX_LENGTH, gl::GetActiveAttrib, gl::GetAttribLocation)
+    }
+}
+
+// Uniforms inside uniform blocks have no location and are left out
+pub fn active_uniforms(program: GLuint) -> Vec<ActiveVariable> {
+    unsafe {
+        active_variables(program, gl::ACTIVE_UNIFORMS, gl::ACTIVE_UNIFORM_MAX_LENGTH, gl::GetActiveUniform, gl::GetUniformLocation)
+    }
+}
+
+unsafe fn active_variables(
+    program: GLuint,
+    count_param: GLenum,
+    length_param: GLenum,
+    get_active: unsafe fn(GLuint, GLuint, GLsizei, *mut GLsizei, *mut GLint, *mut GLenum, *mut GLchar),
+    get_location: unsafe fn(GLuint, *const GLchar) -> GLint,
+) -> Vec<ActiveVariable> {
+    let mut count = 0;
+    let mut max_length = 0;
+    gl::GetProgramiv(program, count_param, &mut count);
+    gl::GetProgramiv(program, length_param, &mut max_length);
+    let mut buf = vec![0u8; max_length.max(1) as usize];
+    let mut variables = Vec::with_capacity(count.max(0) as usize);
+    for i in 0..count.max(0) as GLuint {
+        let (mut length, mut size, mut kind): (GLsizei, GLint, GLenum) = (0, 0, 0);
+        get_active(program, i, buf.len() as GLsizei, &mut length, &mut size, &mut kind, buf.as_mut_ptr() as *mut GLchar);
+        // the index of an active variable isn't its location, e.g. blocks and samplers shift them
+        let location = get_location(program, buf.as_ptr() as *const GLchar);
+        if location < 0 {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&buf[..length.max(0) as usize]);
+        let name = name.strip_suffix("[0]").unwrap_or(&name).to_string();
+        variables.push(ActiveVariable { name, location, kind, size });
+    }
+    variables
+}
+
+// Compare what the program reads with what the Rust side feeds it, one line per problem
+// attributes and uniforms the driver optimized out are fine, anything active has to be provided with the right type
+pub fn validate(program: GLuint, layout: &[VertexAttribute], uniforms: &[(String, GLenum)]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for attribute in active_attributes(program) {
+        if attribute.name.starts_with("gl_") {
+            continue; // gl_VertexID and gl_InstanceID show up as attributes on some drivers
+        }
+        match layout.iter().find(|a| a.location as GLint == attribute.location) {
+            None => problems.push(format!("attribute {} {} at location {} isn't in the vertex layout",
+                                          type_name(attribute.kind), attribute.name, attribute.location)),
+            Some(expected) if float_type(expected.components) != Some(attribute.kind) => {
+                problems.push(format!("attribute {} {} at location {} is fed {} floats of {}",
+                                      type_name(attribute.kind), attribute.name, attribute.location, expected.components, expected.name))
+            }
+            Some(_) => {}
+        }
+    }
+    for uniform in active_uniforms(program) {
+        match uniforms.iter().find(|(name, _)| *name == uniform.name) {
+            None => problems.push(format!("uniform {} {} is never set", type_name(uniform.kind), uniform.name)),
+            Some(&(_, kind)) if kind != uniform.kind => {
+                problems.push(format!("uniform {} {} is set as {}", type_name(uniform.kind), uniform.name, type_name(kind)))
+            }
+            Some(_) => {}
+        }
+    }
+    problems
+}
+
+fn float_type(components: GLint) -> Option<GLenum> {
+    match components {
+        1 => Some(gl::FLOAT),
+        2 => Some(gl::FLOAT_VEC2),
+        3 => Some(gl::FLOAT_VEC3),
+        4 => Some(gl::FLOAT_VEC4),
+        _ => None,
+    }
+}
+
+// GLSL spelling of the types the shaders here use
+pub fn type_name(kind: GLenum) -> &'static str {
+    match kind {
+        gl::FLOAT => "float",
+        gl::FLOAT_VEC2 => "vec2",
+        gl::FLOAT_VEC3 => "vec3",
+        gl::FLOAT_VEC4 => "vec4",
+        gl::INT => "int",
+        gl::INT_VEC2 => "ivec2",
+        gl::INT_VEC3 => "ivec3",
+        gl::INT_VEC4 => "ivec4",
+        gl::UNSIGNED_INT => "uint",
+        gl::BOOL => "bool",
+        gl::FLOAT_MAT3 => "mat3",
+        gl::FLOAT_MAT4 => "mat4",
+        gl::SAMPLER_2D => "sampler2D",
+        gl::SAMPLER_BUFFER => "samplerBuffer",
+        _ => "?",
+    }
+}
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{Matrix, Matrix4};
+use gl::types::{GLint, GLsizei, GLuint};
+
+use crate::shader_interface;
+
+// Linked program with its uniform locations looked up once
+// the setters act on the program in use, call use_program first like with plain gl::Uniform*
+pub struct ShaderProgram {
+    id: GLuint,
+    uniforms: HashMap<String, GLint>,
+    unknown: RefCell<HashSet<String>>, // names already warned about
+}
+
+impl ShaderProgram {
+    // Takes ownership of a linked program, it's deleted on drop
+    pub fn new(id: GLuint) -> Self {
+        ShaderProgram { id, uniforms: shader_interface::active_uniforms(id).into_iter().map(|u| (u.name, u.location)).collect(), unknown: RefCell::new(HashSet::new()) }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn use_program(&self) {
+        unsafe { gl::UseProgram(self.id) };
+    }
+
+    // Location of an active uniform, struct fields as "light.position" and arrays by their plain name
+    // unknown names are misspelled or optimized out by the driver, they're reported once and then ignored
+    pub fn location(&self, name: &str) -> Option<GLint> {
+        let location = self.uniforms.get(name).copied();
+        if location.is_none() && self.unknown.borrow_mut().insert(name.to_string()) {
+            eprintln!("Program {} has no active uniform '{}', setting it does nothing", self.id, name);
+        }
+        location
+    }
+
+    pub fn set_mat4(&self, name: &str, value: &Matrix4<f32>) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::UniformMatrix4fv(location, 1, gl::FALSE, value.as_ptr()) };
+        }
+    }
+
+    pub fn set_f32(&self, name: &str, value: f32) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1f(location, value) };
+        }
+    }
+
+    pub fn set_i32(&self, name: &str, value: i32) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1i(location, value) };
+        }
+    }
+
+    pub fn set_u32(&self, name: &str, value: u32) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1ui(location, value) };
+        }
+    }
+
+    pub fn set_bool(&self, name: &str, value: bool) {
+        self.set_i32(name, value as i32);
+    }
+
+    pub fn set_vec2(&self, name: &str, value: [f32; 2]) {
+        self.set_vec2_array(name, &[value]);
+    }
+
+    pub fn set_vec3(&self, name: &str, value: [f32; 3]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform3fv(location, 1, value.as_ptr()) };
+        }
+    }
+
+    pub fn set_vec4(&self, name: &str, value: [f32; 4]) {
+        self.set_vec4_array(name, &[value]);
+    }
+
+    pub fn set_i32_array(&self, name: &str, values: &[i32]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform1iv(location, values.len() as GLsizei, values.as_ptr()) };
+        }
+    }
+
+    pub fn set_vec2_array(&self, name: &str, values: &[[f32; 2]]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform2fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) };
+        }
+    }
+
+    pub fn set_vec4_array(&self, name: &str, values: &[[f32; 4]]) {
+        if let Some(location) = self.location(name) {
+            unsafe { gl::Uniform4fv(location, values.len() as GLsizei, values.as_ptr() as *const f32) };
+        }
+    }
+}
+
+impl Drop for ShaderProgram {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+    }
+}
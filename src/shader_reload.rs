@@ -0,0 +1,139 @@
+use std::cell::RefCell;
+use std::fs;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use gl::types::GLuint;
+
+use crate::shader_program::ShaderProgram;
+use crate::shader_utils::{ProgramBuilder, ShaderError};
+
+// Where the shaders are read from in dev mode, the source tree this binary was built from
+pub const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static HOT_RELOAD: AtomicBool = AtomicBool::new(false);
+
+// Dev mode: read the shaders from SHADER_DIR instead of the copies built into the binary and reload them on change
+// turn it on before creating any programs
+pub fn enable() {
+    HOT_RELOAD.store(true, Ordering::Relaxed);
+    println!("Loading shaders from {}, edits are picked up while running", SHADER_DIR);
+}
+
+pub fn enabled() -> bool {
+    HOT_RELOAD.load(Ordering::Relaxed)
+}
+
+// The shaders as they were when the binary was built
+pub fn embedded_source(name: &str) -> Option<&'static str> {
+    match name {
+        "vertex.glsl" => Some(include_str!("../shaders/vertex.glsl")),
+        "fragment.glsl" => Some(include_str!("../shaders/fragment.glsl")),
+        "lines_v.glsl" => Some(include_str!("../shaders/lines_v.glsl")),
+        "lines_f.glsl" => Some(include_str!("../shaders/lines_f.glsl")),
+        "markers_v.glsl" => Some(include_str!("../shaders/markers_v.glsl")),
+        "markers_f.glsl" => Some(include_str!("../shaders/markers_f.glsl")),
+        "particles_update_v.glsl" => Some(include_str!("../shaders/particles_update_v.glsl")),
+        "particles_v.glsl" => Some(include_str!("../shaders/particles_v.glsl")),
+        "particles_f.glsl" => Some(include_str!("../shaders/particles_f.glsl")),
+        "trails_v.glsl" => Some(include_str!("../shaders/trails_v.glsl")),
+        "trails_f.glsl" => Some(include_str!("../shaders/trails_f.glsl")),
+        "lighting.glsl" => Some(include_str!("../shaders/lighting.glsl")),
+        "emitters.glsl" => Some(include_str!("../shaders/emitters.glsl")),
+        _ => None,
+    }
+}
+
+pub(crate) fn disk_source(name: &str) -> Result<String, ShaderError> {
+    fs::read_to_string(Path::new(SHADER_DIR).join(name)).map_err(|e| ShaderError::Source { file: name.to_string(), reason: e.to_string() })
+}
+
+fn built_in_source(name: &str) -> Result<String, ShaderError> {
+    embedded_source(name).map(str::to_string).ok_or_else(|| ShaderError::Source { file: name.to_string(), reason: "not built in".to_string() })
+}
+
+fn modified_time(name: &str) -> Option<SystemTime> {
+    fs::metadata(Path::new(SHADER_DIR).join(name)).and_then(|m| m.modified()).ok()
+}
+
+// Program built from files in the shaders directory, relinked when one of them changes in dev mode
+// a failed rebuild prints the log and keeps the last program that worked
+pub struct HotProgram {
+    program: ShaderProgram,
+    builder: ProgramBuilder,
+    watched: Vec<String>, // the stages and everything they include
+    modified: Vec<Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+impl HotProgram {
+    pub fn new(builder: ProgramBuilder) -> Result<Self, ShaderError> {
+        let (id, watched) = if enabled() {
+            // nothing to keep yet, a broken file on disk falls back to the built in copy
+            let (result, watched) = build_watched(&builder, disk_source);
+            let id = result.or_else(|e| {
+                eprintln!("{}\nUsing the built in shaders instead", e);
+                builder.build(built_in_source)
+            })?;
+            (id, watched)
+        } else {
+            (builder.build(built_in_source)?, Vec::new())
+        };
+        let modified = watched.iter().map(|name| modified_time(name)).collect();
+        Ok(HotProgram { program: ShaderProgram::new(id), builder, watched, modified, last_poll: Instant::now() })
+    }
+
+    // Rebuild if any of the files changed since the last look, true when the program was replaced
+    // uniforms set on the old program are gone, callers that only set them once must set them again
+    pub fn reload_if_changed(&mut self) -> bool {
+        if !enabled() || self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let modified: Vec<Option<SystemTime>> = self.watched.iter().map(|name| modified_time(name)).collect();
+        if modified == self.modified {
+            return false;
+        }
+
+        let (result, watched) = build_watched(&self.builder, disk_source);
+        // an edit can add or drop includes
+        self.modified = watched.iter().map(|name| modified_time(name)).collect();
+        self.watched = watched;
+        let names = self.builder.files();
+        match result {
+            Ok(id) => {
+                self.program = ShaderProgram::new(id); // drops the old one
+                println!("Reloaded {}", names.join(" + "));
+                true
+            }
+            Err(e) => {
+                eprintln!("{}\nKeeping the previous {} program", e, names.join(" + "));
+                false
+            }
+        }
+    }
+}
+
+impl Deref for HotProgram {
+    type Target = ShaderProgram;
+
+    fn deref(&self) -> &ShaderProgram {
+        &self.program
+    }
+}
+
+// Build and note down every file that was read along the way, includes too
+fn build_watched(builder: &ProgramBuilder, source: fn(&str) -> Result<String, ShaderError>) -> (Result<GLuint, ShaderError>, Vec<String>) {
+    let read = RefCell::new(builder.files().iter().map(|name| name.to_string()).collect::<Vec<_>>());
+    let result = builder.build(|name| {
+        let mut read = read.borrow_mut();
+        if !read.iter().any(|n| n == name) {
+            read.push(name.to_string());
+        }
+        source(name)
+    });
+    (result, read.into_inner())
+}
@@ -1,14 +1,182 @@
 extern crate gl;
 use gl::types::*;
-use std::ffi::CString;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::ptr;
-use std::str;
 
-// Function to compile a shader
-pub(crate) fn compile_shader(src: &str, ty: GLenum) -> GLuint {
+use crate::shader_interface::{self, VertexAttribute};
+
+// Everything that can go wrong building a program, Display gives a report with the offending source lines
+#[derive(Debug)]
+pub enum ShaderError {
+    // the file couldn't be read, or isn't one of the built in shaders
+    Source { file: String, reason: String },
+    // `origins` has the file and line every line of the preprocessed `source` came from
+    Compile { stage: GLenum, file: String, source: String, origins: Vec<(String, usize)>, log: String },
+    Link { files: Vec<String>, log: String },
+    // linked, but the attributes or uniforms don't match what the Rust side declared for it
+    Interface { files: Vec<String>, problems: Vec<String> },
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShaderError::Source { file, reason } => write!(f, "Could not load shader {}: {}", file, reason),
+            ShaderError::Compile { stage, file, source, origins, log } => {
+                write!(f, "Failed to compile {} shader {}:", stage_name(*stage), file)?;
+                let lines: Vec<&str> = source.lines().collect();
+                for message in log.lines().filter(|l| !l.trim().is_empty()) {
+                    write!(f, "\n  {}", message.trim_end())?;
+                    if let Some(line) = error_line(message).filter(|&l| l >= 1 && l <= lines.len()) {
+                        // the driver counts lines of the preprocessed source, point at the file they came from instead
+                        let (origin, origin_line) = origins.get(line - 1).map_or((file.as_str(), line), |(o, l)| (o.as_str(), *l));
+                        write!(f, "\n  {}:{} | {}", origin, origin_line, lines[line - 1])?;
+                    }
+                }
+                Ok(())
+            }
+            ShaderError::Link { files, log } => {
+                write!(f, "Failed to link {}:", files.join(" + "))?;
+                for message in log.lines().filter(|l| !l.trim().is_empty()) {
+                    write!(f, "\n  {}", message.trim_end())?;
+                }
+                Ok(())
+            }
+            ShaderError::Interface { files, problems } => {
+                write!(f, "Interface of {} doesn't match the Rust side:", files.join(" + "))?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+pub fn stage_name(stage: GLenum) -> &'static str {
+    match stage {
+        gl::VERTEX_SHADER => "vertex",
+        gl::FRAGMENT_SHADER => "fragment",
+        gl::GEOMETRY_SHADER => "geometry",
+        gl::TESS_CONTROL_SHADER => "tessellation control",
+        gl::TESS_EVALUATION_SHADER => "tessellation evaluation",
+        _ => "unknown",
+    }
+}
+
+// Source line a driver message points at, every driver formats it differently:
+// Mesa "0:12(3): error", NVIDIA "0(12) : error", AMD and Intel "ERROR: 0:12: ..."
+fn error_line(message: &str) -> Option<usize> {
+    let bytes = message.as_bytes();
+    let digits_from = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        i
+    };
+    for start in 0..bytes.len() {
+        if !bytes[start].is_ascii_digit() || (start > 0 && bytes[start - 1].is_ascii_digit()) {
+            continue;
+        }
+        // the first number is the source string index, the line follows it
+        let separator = digits_from(start);
+        let Some(&open) = bytes.get(separator) else { break };
+        if open != b':' && open != b'(' {
+            continue;
+        }
+        let end = digits_from(separator + 1);
+        if end == separator + 1 || (open == b'(' && bytes.get(end) != Some(&b')')) {
+            continue;
+        }
+        return message[separator + 1..end].parse().ok();
+    }
+    None
+}
+
+// Source with the includes pasted in and the defines added, and where every line came from
+pub struct Preprocessed {
+    pub source: String,
+    pub origins: Vec<(String, usize)>, // file and line number, the defines are listed as <defines>
+}
+
+impl Preprocessed {
+    fn push(&mut self, line: &str, file: &str, number: usize) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.origins.push((file.to_string(), number));
+    }
+}
+
+// Resolve `#include "file.glsl"` lines with the same lookup as `file` itself, recursively,
+// and put a `#define name value` for each of `defines` right after the #version line
+pub fn preprocess(file: &str, defines: &[(String, String)], source: &dyn Fn(&str) -> Result<String, ShaderError>) -> Result<Preprocessed, ShaderError> {
+    let mut out = Preprocessed { source: String::new(), origins: Vec::new() };
+    expand(file, Some(defines), source, &mut Vec::new(), &mut out)?;
+    Ok(out)
+}
+
+fn expand(
+    file: &str,
+    mut defines: Option<&[(String, String)]>, // only for the top level file
+    source: &dyn Fn(&str) -> Result<String, ShaderError>,
+    stack: &mut Vec<String>, // files being expanded, to catch includes of themselves
+    out: &mut Preprocessed,
+) -> Result<(), ShaderError> {
+    if stack.iter().any(|f| f == file) {
+        let chain: Vec<&str> = stack.iter().map(String::as_str).chain([file]).collect();
+        return Err(ShaderError::Source { file: stack[0].clone(), reason: format!("include cycle {}", chain.join(" -> ")) });
+    }
+    let text = source(file)?;
+    let emit_defines = |defines: &[(String, String)], out: &mut Preprocessed| {
+        for (i, (name, value)) in defines.iter().enumerate() {
+            out.push(&format!("#define {} {}", name, value), "<defines>", i + 1);
+        }
+    };
+    // nothing but comments and whitespace may come before #version, without one the defines go first
+    if !text.lines().any(|l| l.trim_start().starts_with("#version")) {
+        if let Some(defines) = defines.take() {
+            emit_defines(defines, out);
+        }
+    }
+
+    stack.push(file.to_string());
+    for (i, line) in text.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if let Some(rest) = trimmed.strip_prefix("#include") {
+            let name = rest.trim().strip_prefix('"').and_then(|r| r.strip_suffix('"')).ok_or_else(|| {
+                ShaderError::Source { file: file.to_string(), reason: format!("expected #include \"file\" on line {}", i + 1) }
+            })?;
+            expand(name, None, source, stack, out)?;
+            continue;
+        }
+        out.push(line, file, i + 1);
+        if trimmed.starts_with("#version") {
+            if let Some(defines) = defines.take() {
+                emit_defines(defines, out);
+            }
+        }
+    }
+    stack.pop();
+    Ok(())
+}
+
+// Compile one shader stage, `file` only labels the errors
+fn compile_shader(src: &Preprocessed, ty: GLenum, file: &str) -> Result<GLuint, ShaderError> {
+    let compile_error = |log: String| ShaderError::Compile {
+        stage: ty,
+        file: file.to_string(),
+        source: src.source.clone(),
+        origins: src.origins.clone(),
+        log,
+    };
+    let c_str = CString::new(src.source.as_bytes()).map_err(|_| compile_error("source contains a null byte".to_string()))?;
     let shader = unsafe { gl::CreateShader(ty) };
     unsafe {
-        let c_str = CString::new(src.as_bytes()).unwrap();
         gl::ShaderSource(shader, 1, &c_str.as_ptr(), ptr::null());
         gl::CompileShader(shader);
 
@@ -16,70 +184,419 @@ pub(crate) fn compile_shader(src: &str, ty: GLenum) -> GLuint {
         let mut status = GLint::from(gl::FALSE);
         gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut status);
         if status != GLint::from(gl::TRUE) {
-            let mut len = 0;
-            gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
-            gl::GetShaderInfoLog(shader, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            let log = str::from_utf8(&buf).ok().expect("ShaderInfoLog not valid utf8");
-            panic!("Failed to compile shader: {}", log);
+            let log = info_log(shader, gl::GetShaderiv, gl::GetShaderInfoLog);
+            gl::DeleteShader(shader);
+            return Err(compile_error(log));
         }
     }
-    shader
+    Ok(shader)
 }
 
-// Function to link shaders into a program
-pub(crate) fn link_program(vertex_shader_id: GLuint, fragment_shader_id: GLuint) -> GLuint {
-    let program = unsafe { gl::CreateProgram() };
-    unsafe {
-        gl::AttachShader(program, vertex_shader_id);
-        gl::AttachShader(program, fragment_shader_id);
-        gl::LinkProgram(program);
+// Everything that goes into a program besides the sources, which are looked up by file name when building
+// so the same builder can be built again from disk or from the copies in the binary
+#[derive(Clone)]
+pub struct ProgramBuilder {
+    stages: Vec<(String, GLenum)>,
+    defines: Vec<(String, String)>,
+    attributes: Vec<(GLuint, String)>,
+    layout: Vec<VertexAttribute>,
+    uniforms: Vec<(String, GLenum)>,
+    varyings: Vec<String>, // captured with transform feedback, empty for none
+    feedback_mode: GLenum,
+    cached: bool,
+}
 
-        // Check for linking error
-        let mut status = GLint::from(gl::FALSE);
-        gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
-        if status != GLint::from(gl::TRUE) {
-            let mut len: GLint = 0;
-            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
-            gl::GetProgramInfoLog(program, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            let log = std::str::from_utf8(&buf).expect("ShaderInfoLog not valid utf8");
-            println!("Shader compilation error: {}", log);
-            panic!();
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        ProgramBuilder { stages: Vec::new(), defines: Vec::new(), attributes: Vec::new(), layout: Vec::new(), uniforms: Vec::new(), varyings: Vec::new(), feedback_mode: gl::INTERLEAVED_ATTRIBS, cached: true }
+    }
+
+    // Any of the vertex, tessellation, geometry and fragment stages, in any order
+    pub fn stage(mut self, file: &str, stage: GLenum) -> Self {
+        self.stages.push((file.to_string(), stage));
+        self
+    }
+
+    pub fn vertex(self, file: &str) -> Self {
+        self.stage(file, gl::VERTEX_SHADER)
+    }
+
+    pub fn fragment(self, file: &str) -> Self {
+        self.stage(file, gl::FRAGMENT_SHADER)
+    }
+
+    // `#define name value` in every stage, right after the #version line, for constants shared with the Rust side
+    pub fn define(mut self, name: &str, value: impl ToString) -> Self {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    // Only needed for shaders without layout qualifiers on their inputs
+    pub fn bind_attribute(mut self, location: GLuint, name: &str) -> Self {
+        self.attributes.push((location, name.to_string()));
+        self
+    }
+
+    // What the program gets fed, checked against its active attributes and uniforms after linking
+    // call vertex_layout once per buffer, none at all for shaders that fetch everything themselves
+    pub fn vertex_layout(mut self, layout: &[VertexAttribute]) -> Self {
+        self.layout.extend_from_slice(layout);
+        self
+    }
+
+    // Struct fields as "light.position", arrays by their plain name
+    pub fn uniforms(mut self, uniforms: &[(&str, GLenum)]) -> Self {
+        self.uniforms.extend(uniforms.iter().map(|&(name, kind)| (name.to_string(), kind)));
+        self
+    }
+
+    // gl::INTERLEAVED_ATTRIBS writes everything into one buffer, gl::SEPARATE_ATTRIBS one buffer per varying
+    pub fn feedback(mut self, varyings: &[&str], mode: GLenum) -> Self {
+        self.varyings = varyings.iter().map(|v| v.to_string()).collect();
+        self.feedback_mode = mode;
+        self
+    }
+
+    // Whether to reuse and store linked binaries in program_cache_dir, on by default
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+
+    pub fn files(&self) -> Vec<&str> {
+        self.stages.iter().map(|(file, _)| file.as_str()).collect()
+    }
+
+    // Compile every stage and link them, or load the binary the driver gave us last time for the same sources
+    pub fn build(&self, source: impl Fn(&str) -> Result<String, ShaderError>) -> Result<GLuint, ShaderError> {
+        let sources = self.stages.iter()
+            .map(|(file, _)| preprocess(file, &self.defines, &source))
+            .collect::<Result<Vec<_>, _>>()?;
+        let cache_file = program_cache_dir()
+            .filter(|_| self.cached && binaries_supported())
+            .map(|dir| dir.join(self.cache_name()));
+        let key = self.cache_key(&sources);
+
+        let program = match cache_file.as_deref().and_then(|path| load_binary(path, key)) {
+            Some(program) => program,
+            None => {
+                let program = self.compile_and_link(&sources)?;
+                if let Some(path) = &cache_file {
+                    store_binary(program, path, key);
+                }
+                program
+            }
+        };
+
+        let problems = shader_interface::validate(program, &self.layout, &self.uniforms);
+        if !problems.is_empty() {
+            unsafe { gl::DeleteProgram(program) };
+            return Err(ShaderError::Interface { files: self.files().iter().map(|f| f.to_string()).collect(), problems });
+        }
+        Ok(program)
+    }
+
+    // The shader objects are gone again afterwards either way
+    fn compile_and_link(&self, sources: &[Preprocessed]) -> Result<GLuint, ShaderError> {
+        let mut shaders = Vec::with_capacity(self.stages.len());
+        let mut result = Ok(());
+        for ((file, stage), src) in self.stages.iter().zip(sources) {
+            match compile_shader(src, *stage, file) {
+                Ok(shader) => shaders.push(shader),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
         }
+        let program = result.and_then(|_| self.link(&shaders));
+        for shader in shaders {
+            unsafe { gl::DeleteShader(shader) };
+        }
+        program
+    }
+
+    // File the binary is kept in, named after which program this is rather than what's in it
+    // so an edited shader overwrites its old binary instead of adding another one
+    fn cache_name(&self) -> String {
+        let mut name = Fnv::new();
+        for (file, stage) in &self.stages {
+            name.write(&stage.to_le_bytes());
+            name.write_str(file);
+        }
+        for (define, value) in &self.defines {
+            name.write_str(define);
+            name.write_str(value);
+        }
+        format!("{:016x}.bin", name.0)
+    }
+
+    // Everything that ends up in the binary plus the driver that made it, a new driver can't load an old binary
+    // stored in the cache file and compared on load
+    fn cache_key(&self, sources: &[Preprocessed]) -> u64 {
+        let mut key = Fnv::new();
+        key.write(&PROGRAM_CACHE_VERSION.to_le_bytes());
+        for name in [gl::VENDOR, gl::RENDERER, gl::VERSION] {
+            key.write_str(&gl_string(name));
+        }
+        for ((_, stage), src) in self.stages.iter().zip(sources) {
+            key.write(&stage.to_le_bytes());
+            key.write_str(&src.source);
+        }
+        for (location, name) in &self.attributes {
+            key.write(&location.to_le_bytes());
+            key.write_str(name);
+        }
+        for varying in &self.varyings {
+            key.write_str(varying);
+        }
+        key.write(&self.feedback_mode.to_le_bytes());
+        key.0
+    }
+
+    fn link(&self, shaders: &[GLuint]) -> Result<GLuint, ShaderError> {
+        let program = unsafe { gl::CreateProgram() };
+        unsafe {
+            if self.cached {
+                gl::ProgramParameteri(program, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as GLint);
+            }
+            for &shader in shaders {
+                gl::AttachShader(program, shader);
+            }
+            for (location, name) in &self.attributes {
+                let c_name = CString::new(name.as_str()).unwrap();
+                gl::BindAttribLocation(program, *location, c_name.as_ptr());
+            }
+            if !self.varyings.is_empty() {
+                let c_varyings: Vec<CString> = self.varyings.iter().map(|v| CString::new(v.as_str()).unwrap()).collect();
+                let c_varyings_ptrs: Vec<*const GLchar> = c_varyings.iter().map(|c_str| c_str.as_ptr()).collect();
+                gl::TransformFeedbackVaryings(program, c_varyings_ptrs.len() as GLsizei, c_varyings_ptrs.as_ptr(), self.feedback_mode);
+            }
+            gl::LinkProgram(program);
+
+            // Check for linking error
+            let mut status = GLint::from(gl::FALSE);
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+            if status != GLint::from(gl::TRUE) {
+                let log = info_log(program, gl::GetProgramiv, gl::GetProgramInfoLog);
+                gl::DeleteProgram(program);
+                return Err(ShaderError::Link { files: self.files().iter().map(|f| f.to_string()).collect(), log });
+            }
+            for &shader in shaders {
+                gl::DetachShader(program, shader);
+            }
+        }
+        Ok(program)
+    }
+}
+
+const PROGRAM_CACHE_VERSION: u32 = 2; // bump when the file layout changes
+
+// Linked programs are kept in the user's cache directory, one file per program, next to nothing if it can't be found
+pub fn program_cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("LOCALAPPDATA")
+        .or_else(|| env::var_os("XDG_CACHE_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(base.join(env!("CARGO_PKG_NAME")).join("programs"))
+}
+
+// Needs GL 4.1 or ARB_get_program_binary, and a driver that offers at least one format
+fn binaries_supported() -> bool {
+    if !gl::GetProgramBinary::is_loaded() || !gl::ProgramBinary::is_loaded() {
+        return false;
+    }
+    let mut formats = 0;
+    unsafe { gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut formats) };
+    formats > 0
+}
+
+// The file is the cache key as 8 little endian bytes, the binary format as 4 and then the binary
+// None when there's no file, it was made from other sources or the driver rejects it,
+// an outdated file is left for store_binary to overwrite, a broken one is removed
+fn load_binary(path: &Path, key: u64) -> Option<GLuint> {
+    let data = fs::read(path).ok()?;
+    if data.len() <= 12 {
+        let _ = fs::remove_file(path);
+        return None;
+    }
+    let (stored_key, rest) = data.split_at(8);
+    if u64::from_le_bytes(stored_key.try_into().unwrap()) != key {
+        return None;
+    }
+    let format = GLenum::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+    let binary = &rest[4..];
+    unsafe {
+        let mut count = 0;
+        gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut count);
+        let mut formats = vec![0; count.max(0) as usize];
+        gl::GetIntegerv(gl::PROGRAM_BINARY_FORMATS, formats.as_mut_ptr());
+        if formats.contains(&(format as GLint)) {
+            let program = gl::CreateProgram();
+            gl::ProgramBinary(program, format, binary.as_ptr() as *const _, binary.len() as GLsizei);
+            let mut status = GLint::from(gl::FALSE);
+            gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
+            if status == GLint::from(gl::TRUE) {
+                return Some(program);
+            }
+            gl::DeleteProgram(program);
+            // some drivers flag the rejected binary as an error too, don't leave it for the next glGetError
+            while gl::GetError() != gl::NO_ERROR {}
+        }
+    }
+    let _ = fs::remove_file(path);
+    None
+}
+
+// Best effort, a program that can't be stored is simply compiled again next time
+fn store_binary(program: GLuint, path: &Path, key: u64) {
+    let mut length = 0;
+    unsafe { gl::GetProgramiv(program, gl::PROGRAM_BINARY_LENGTH, &mut length) };
+    if length <= 0 {
+        return;
+    }
+    let mut binary = vec![0u8; length as usize];
+    let (mut written, mut format): (GLsizei, GLenum) = (0, 0);
+    unsafe { gl::GetProgramBinary(program, length, &mut written, &mut format, binary.as_mut_ptr() as *mut _) };
+    binary.truncate(written.max(0) as usize);
+    if binary.is_empty() {
+        return;
+    }
+    let mut data = key.to_le_bytes().to_vec();
+    data.extend_from_slice(&format.to_le_bytes());
+    data.extend_from_slice(&binary);
+    // written next to the final name and renamed, so a crash halfway never leaves a truncated binary behind
+    let temp = path.with_extension("tmp");
+    let stored = path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temp, &data))
+        .and_then(|_| fs::rename(&temp, path));
+    if stored.is_err() {
+        let _ = fs::remove_file(&temp);
     }
-    program
 }
 
-pub(crate) fn link_particles_program(vertex_shader_id: GLuint, fragment_shader_id: GLuint) -> GLuint {
-    let program = unsafe { gl::CreateProgram() };
+fn gl_string(name: GLenum) -> String {
     unsafe {
-        gl::AttachShader(program, vertex_shader_id);
-        gl::AttachShader(program, fragment_shader_id);
+        let string = gl::GetString(name);
+        if string.is_null() {
+            return String::new();
+        }
+        CStr::from_ptr(string as *const _).to_string_lossy().into_owned()
+    }
+}
 
-        // Specify the output of the vertex shader we want to capture in transform feedback
-        let varyings = ["outPosition", "outVelocity"];
-        let c_varyings: Vec<CString> = varyings.iter().map(|&v| CString::new(v).unwrap()).collect();
-        let c_varyings_ptrs: Vec<*const GLchar> = c_varyings.iter().map(|c_str| c_str.as_ptr()).collect();
+// FNV-1a, unlike DefaultHasher it gives the same key in every build
+struct Fnv(u64);
 
-        gl::TransformFeedbackVaryings(program, 2, c_varyings_ptrs.as_ptr(), gl::INTERLEAVED_ATTRIBS);
-        gl::LinkProgram(program);
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
 
-        // Check for linking error
-        let mut status = GLint::from(gl::FALSE);
-        gl::GetProgramiv(program, gl::LINK_STATUS, &mut status);
-        if status != GLint::from(gl::TRUE) {
-            let mut len: GLint = 0;
-            gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
-            gl::GetProgramInfoLog(program, len, ptr::null_mut(), buf.as_mut_ptr() as *mut GLchar);
-            let log = std::str::from_utf8(&buf).expect("ShaderInfoLog not valid utf8");
-            println!("Shader compilation error: {}", log);
-            panic!();
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
         }
     }
-    program
+
+    // length first so "ab" + "c" and "a" + "bc" differ
+    fn write_str(&mut self, s: &str) {
+        self.write(&(s.len() as u64).to_le_bytes());
+        self.write(s.as_bytes());
+    }
+}
+
+// Info log of a shader or program, empty when the driver has nothing to say
+unsafe fn info_log(
+    object: GLuint,
+    get_iv: unsafe fn(GLuint, GLenum, *mut GLint),
+    get_log: unsafe fn(GLuint, GLsizei, *mut GLsizei, *mut GLchar),
+) -> String {
+    let mut len: GLint = 0;
+    get_iv(object, gl::INFO_LOG_LENGTH, &mut len);
+    if len <= 0 {
+        return String::new();
+    }
+    let mut buf = vec![0u8; len as usize];
+    let mut written: GLsizei = 0;
+    get_log(object, len, &mut written, buf.as_mut_ptr() as *mut GLchar);
+    buf.truncate(written.max(0) as usize); // the driver leaves out the trailing null character
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // in-memory files instead of the shader directory, so none of this needs a GL context
+    fn files(files: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Result<String, ShaderError> {
+        move |name| {
+            files.iter().find(|(file, _)| *file == name).map(|(_, text)| text.to_string())
+                .ok_or_else(|| ShaderError::Source { file: name.to_string(), reason: "not found".to_string() })
+        }
+    }
+
+    #[test]
+    fn mesa_error_line() {
+        assert_eq!(error_line("0:12(3): error: `foo' undeclared"), Some(12));
+    }
+
+    #[test]
+    fn nvidia_error_line() {
+        assert_eq!(error_line("0(12) : error C1008: undefined variable \"foo\""), Some(12));
+    }
+
+    #[test]
+    fn amd_intel_error_line() {
+        assert_eq!(error_line("ERROR: 0:12: 'foo' : undeclared identifier"), Some(12));
+    }
+
+    #[test]
+    fn message_without_a_line() {
+        assert_eq!(error_line("error: linking failed"), None);
+    }
+
+    #[test]
+    fn include_is_pasted_in() {
+        let source = files(&[
+            ("main.glsl", "#version 330\n#include \"common.glsl\"\nvoid main() {}"),
+            ("common.glsl", "float half(float x) { return x * 0.5; }"),
+        ]);
+        let preprocessed = preprocess("main.glsl", &[], &source).unwrap();
+        assert_eq!(preprocessed.source, "#version 330\nfloat half(float x) { return x * 0.5; }\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn origins_point_past_the_include() {
+        let source = files(&[
+            ("main.glsl", "#version 330\n#include \"common.glsl\"\nvoid main() {}"),
+            ("common.glsl", "// shared\nfloat half(float x) { return x * 0.5; }"),
+        ]);
+        let defines = [("COUNT".to_string(), "4".to_string())];
+        let preprocessed = preprocess("main.glsl", &defines, &source).unwrap();
+        let origins: Vec<(&str, usize)> = preprocessed.origins.iter().map(|(file, line)| (file.as_str(), *line)).collect();
+        assert_eq!(origins, [("main.glsl", 1), ("<defines>", 1), ("common.glsl", 1), ("common.glsl", 2), ("main.glsl", 3)]);
+        assert_eq!(preprocessed.source.lines().nth(4), Some("void main() {}"));
+    }
+
+    #[test]
+    fn self_include_is_a_cycle() {
+        let source = files(&[("loop.glsl", "#version 330\n#include \"loop.glsl\"")]);
+        match preprocess("loop.glsl", &[], &source) {
+            Err(ShaderError::Source { file, reason }) => {
+                assert_eq!(file, "loop.glsl");
+                assert!(reason.contains("loop.glsl -> loop.glsl"), "{}", reason);
+            }
+            _ => panic!("expected an include cycle error"),
+        }
+    }
+
+    #[test]
+    fn same_file_included_twice_is_not_a_cycle() {
+        let source = files(&[
+            ("main.glsl", "#include \"a.glsl\"\n#include \"a.glsl\""),
+            ("a.glsl", "// a"),
+        ]);
+        assert_eq!(preprocess("main.glsl", &[], &source).unwrap().source, "// a\n// a\n");
+    }
 }
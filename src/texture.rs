@@ -1,45 +1,169 @@
-use image::io::Reader as ImageReader;
-use image::DynamicImage;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
 
-fn load_image(file_path: &str) -> DynamicImage {
-    ImageReader::open(file_path)
-        .expect("Failed to open texture file")
-        .decode()
-        .expect("Failed to decode image")
+use gl::types::{GLint, GLsizei, GLuint};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+const UPLOAD_BYTES_PER_FRAME: usize = 4 << 20; // a few ms of the frame, the earth texture takes a handful of frames
+const PLACEHOLDER_COLOR: [u8; 4] = [110, 120, 130, 255]; // until the image is decoded
+const PREVIEW_SIZE: (u32, u32) = (256, 128); // small enough to upload at once
+
+// Grey placeholder squares, for textures that couldn't be loaded
+pub fn checkerboard(width: u32, height: u32, square: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        if (x / square + y / square) % 2 == 0 { Rgba([200, 200, 200, 255]) } else { Rgba([90, 90, 90, 255]) }
+    }))
 }
 
-pub fn create_texture(file_path: &str) -> u32 {
+// Pixels as the GL texture wants them, on a large texture this takes about as long as the decoding
+fn prepare(image: DynamicImage) -> RgbaImage {
+    image.fliph().into_rgba8()
+}
 
-    // 1000ms for large texture
-    let image = load_image(file_path);
-    // 1000ms for large texture
-    let img = image.fliph().into_rgba8();
-    let (width, height) = img.dimensions();
+// What the worker sends, the preview first and then the full image
+enum Decoded {
+    Preview(RgbaImage),
+    Full(RgbaImage),
+}
 
-    let mut texture_id = 0;
-    unsafe {
-        gl::GenTextures(1, &mut texture_id);
-        gl::BindTexture(gl::TEXTURE_2D, texture_id);
-
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
-            0,
-            gl::RGBA as i32,
-            width as i32,
-            height as i32,
-            0,
-            gl::RGBA,
-            gl::UNSIGNED_BYTE,
-            img.as_ptr() as *const _,
-        );
-
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
-
-        gl::GenerateMipmap(gl::TEXTURE_2D);
+// Texture decoded on a worker thread, shows a grey placeholder until the image is decoded and a downscaled copy of it
+// while the full image is uploaded a few rows per frame into a second texture, which replaces the preview when it's complete
+pub struct StreamingTexture {
+    id: GLuint, // the one to bind, changes when the preview arrives and when the upload finishes
+    receiver: Option<Receiver<Decoded>>,
+    upload: Option<Upload>,
+}
+
+struct Upload {
+    texture: GLuint,
+    image: RgbaImage,
+    next_row: u32,
+}
+
+impl StreamingTexture {
+    // `decode` runs on its own thread along with the scaling and conversion, everything GL stays on this one
+    pub fn spawn(decode: impl FnOnce() -> DynamicImage + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let image = decode();
+            let (width, height) = PREVIEW_SIZE;
+            // the receiver is gone if the texture was dropped while loading
+            if sender.send(Decoded::Preview(prepare(image.thumbnail(width, height)))).is_ok() {
+                let _ = sender.send(Decoded::Full(prepare(image)));
+            }
+        });
+        let placeholder = RgbaImage::from_pixel(2, 1, Rgba(PLACEHOLDER_COLOR));
+        let id = unsafe { allocate(2, 1, placeholder.as_ptr() as *const _) };
+        StreamingTexture { id, receiver: Some(receiver), upload: None }
+    }
+
+    pub fn id(&self) -> GLuint {
+        self.id
     }
 
+    // Call once per frame on the GL thread, true when id() changed and the texture has to be bound again
+    pub fn update(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(receiver) = &self.receiver {
+            match receiver.try_recv() {
+                Ok(Decoded::Preview(image)) => {
+                    let preview = unsafe { allocate(image.width(), image.height(), image.as_ptr() as *const _) };
+                    self.replace(preview);
+                    changed = true;
+                }
+                Ok(Decoded::Full(image)) => {
+                    let texture = unsafe { allocate(image.width(), image.height(), std::ptr::null()) };
+                    self.upload = Some(Upload { texture, image, next_row: 0 });
+                    self.receiver = None;
+                }
+                Err(TryRecvError::Empty) => return changed,
+                Err(TryRecvError::Disconnected) => {
+                    eprintln!("Texture loading thread stopped without an image, keeping the placeholder");
+                    self.receiver = None;
+                    return changed;
+                }
+            }
+        }
+        let Some(upload) = &mut self.upload else { return changed };
+
+        let (width, height) = upload.image.dimensions();
+        let row_bytes = width as usize * 4;
+        let rows = (UPLOAD_BYTES_PER_FRAME / row_bytes.max(1)).max(1) as u32;
+        let end = (upload.next_row + rows).min(height);
+        unsafe {
+            // whatever is bound on the active unit right now is most likely the placeholder in use
+            let mut bound = 0;
+            gl::GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound);
+            gl::BindTexture(gl::TEXTURE_2D, upload.texture);
+            if end > upload.next_row {
+                gl::TexSubImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    0,
+                    upload.next_row as GLint,
+                    width as GLsizei,
+                    (end - upload.next_row) as GLsizei,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    upload.image.as_ptr().add(upload.next_row as usize * row_bytes) as *const _,
+                );
+            }
+            gl::BindTexture(gl::TEXTURE_2D, bound as GLuint);
+        }
+        upload.next_row = end;
+        if end < height {
+            return changed;
+        }
+
+        let texture = upload.texture;
+        self.upload = None;
+        self.replace(texture);
+        true
+    }
+
+    fn replace(&mut self, texture: GLuint) {
+        unsafe { gl::DeleteTextures(1, &self.id) };
+        self.id = texture;
+    }
+}
+
+impl Drop for StreamingTexture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+            if let Some(upload) = &self.upload {
+                gl::DeleteTextures(1, &upload.texture);
+            }
+        }
+    }
+}
+
+// RGBA texture with the sphere's sampling, `pixels` may be null to fill it in later
+// no mipmaps, the sphere shader only reads level 0
+unsafe fn allocate(width: u32, height: u32, pixels: *const std::ffi::c_void) -> GLuint {
+    let mut texture_id = 0;
+    let mut bound = 0;
+    gl::GetIntegerv(gl::TEXTURE_BINDING_2D, &mut bound);
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id);
+
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        gl::RGBA as i32,
+        width as i32,
+        height as i32,
+        0,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        pixels,
+    );
+
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+
+    gl::BindTexture(gl::TEXTURE_2D, bound as GLuint);
     texture_id
 }
@@ -0,0 +1,144 @@
+use cgmath::Matrix4;
+use gl::types::{GLenum, GLfloat, GLint, GLintptr, GLsizei, GLsizeiptr, GLuint};
+
+use crate::particles::{self, ParticleConfig, FLOATS_PER_PARTICLE, MAX_EMITTERS};
+use crate::shader_reload::HotProgram;
+use crate::shader_utils::{ProgramBuilder, ShaderError};
+
+pub const MAX_TRAIL_PARTICLES: usize = 1 << 16; // about 15 MB of ring buffer at a trail length of 8
+
+const TRAIL_UNIFORMS: [(&str, GLenum); 10] = [
+    ("model", gl::FLOAT_MAT4), ("view", gl::FLOAT_MAT4), ("projection", gl::FLOAT_MAT4),
+    ("trail", gl::SAMPLER_BUFFER), ("particleCount", gl::INT), ("trailLength", gl::INT), ("head", gl::INT), ("filled", gl::INT),
+    ("trailWidth", gl::FLOAT), ("viewportSize", gl::FLOAT_VEC2),
+];
+
+// Fading trails behind the particles
+// every snapshot copies the whole particle buffer into the next layer of a ring buffer on the GPU,
+// the trails are drawn as ribbons through the layers, read back through a buffer texture one float at a time
+pub struct ParticleTrails {
+    program: HotProgram,
+    vao: GLuint, // empty, the vertex shader fetches everything from the buffer texture
+    buffer: GLuint,
+    texture: GLuint,
+    count: usize,
+    length: usize,
+    head: usize,
+    filled: usize,
+    interval: f32,
+    since_snapshot: f32,
+    width: f32,
+}
+
+impl ParticleTrails {
+    pub fn new(count: usize, length: usize, interval: f32, width: f32) -> Result<Self, ShaderError> {
+        let program = HotProgram::new(trail_shaders())?;
+
+        // each snapshot of a particle takes FLOATS_PER_PARTICLE texels, trails past the buffer texture limit would read garbage
+        let mut max_texels: GLint = 0;
+        unsafe { gl::GetIntegerv(gl::MAX_TEXTURE_BUFFER_SIZE, &mut max_texels) };
+        let max_count = max_texels.max(0) as usize / (length.max(1) * FLOATS_PER_PARTICLE);
+        if count > max_count {
+            eprintln!("Buffer textures are limited to {} texels, only the first {} of {} particles get trails", max_texels, max_count, count);
+        }
+        let count = count.min(max_count);
+
+        let mut vao = 0;
+        let mut buffer = 0;
+        let mut texture = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER,
+                           (length * count * FLOATS_PER_PARTICLE * std::mem::size_of::<GLfloat>()) as GLsizeiptr,
+                           std::ptr::null(),
+                           gl::DYNAMIC_COPY);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_BUFFER, texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, gl::R32F, buffer);
+            gl::BindTexture(gl::TEXTURE_BUFFER, 0);
+        }
+
+        Ok(ParticleTrails { program, vao, buffer, texture, count, length, head: 0, filled: 0, interval, since_snapshot: interval, width })
+    }
+
+    pub fn reload_shaders(&mut self) {
+        self.program.reload_if_changed();
+    }
+
+    // Copy the active part of the particle buffer into the ring once per interval
+    pub fn capture(&mut self, particle_buffer: GLuint, active: usize, delta_seconds: f32) {
+        self.since_snapshot += delta_seconds;
+        if self.since_snapshot < self.interval {
+            return;
+        }
+        self.since_snapshot = 0.0;
+        self.head = (self.head + 1) % self.length;
+        self.filled = (self.filled + 1).min(self.length);
+
+        let particle_size = FLOATS_PER_PARTICLE * std::mem::size_of::<GLfloat>();
+        let (layer_size, copy_size) = (self.count * particle_size, active.min(self.count) * particle_size);
+        unsafe {
+            gl::BindBuffer(gl::COPY_READ_BUFFER, particle_buffer);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.buffer);
+            gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, (self.head * layer_size) as GLintptr, copy_size as GLsizeiptr);
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    // Blending and depth writes are set up by the caller, same as for the particles
+    pub fn draw(&self, view: &Matrix4<f32>, projection: &Matrix4<f32>, model: &Matrix4<f32>, config: &ParticleConfig, emitter_ends: &[i32], active: usize) {
+        if self.filled < 2 {
+            return;
+        }
+        self.program.use_program();
+        self.program.set_mat4("view", view);
+        self.program.set_mat4("projection", projection);
+        self.program.set_mat4("model", model);
+        particles::set_emitter_render_uniforms(&self.program, config, emitter_ends);
+
+        let mut viewport = [0; 4];
+        unsafe { gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr()) };
+        self.program.set_vec2("viewportSize", [viewport[2] as f32, viewport[3] as f32]);
+        self.program.set_i32("particleCount", self.count as GLint);
+        self.program.set_i32("trailLength", self.length as GLint);
+        self.program.set_i32("head", self.head as GLint);
+        self.program.set_i32("filled", self.filled as GLint);
+        self.program.set_f32("trailWidth", self.width);
+        // texture unit 3, after the sphere texture, heightmap and particle sprite
+        self.program.set_i32("trail", 3);
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_BUFFER, self.texture);
+            gl::ActiveTexture(gl::TEXTURE0);
+
+            gl::BindVertexArray(self.vao);
+            gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, (2 * self.length) as GLsizei, active.min(self.count) as GLsizei);
+            gl::BindVertexArray(0);
+        }
+    }
+}
+
+// No vertex layout, the vertex shader fetches everything from the buffer texture
+pub fn trail_shaders() -> ProgramBuilder {
+    ProgramBuilder::new()
+        .vertex("trails_v.glsl")
+        .fragment("trails_f.glsl")
+        .define("MAX_EMITTERS", MAX_EMITTERS)
+        .uniforms(&TRAIL_UNIFORMS)
+        .uniforms(&particles::EMITTER_RENDER_UNIFORMS)
+}
+
+impl Drop for ParticleTrails {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.buffer);
+            gl::DeleteTextures(1, &self.texture);
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use gl::types::{GLint, GLsizei, GLuint};
+
+// Wind on an equirectangular grid, u eastward and v northward in m/s
+// row 0 is the northernmost, column 0 starts at 180W, values sit at the cell centres
+#[derive(Clone, Debug)]
+pub struct WindField {
+    pub width: usize,
+    pub height: usize,
+    pub u: Vec<f32>,
+    pub v: Vec<f32>,
+    pub max_speed: f32,
+}
+
+impl WindField {
+    // Bilinear between the four nearest cell centres, wrapping around in longitude
+    // same arithmetic as windAt in particles_update_v.glsl
+    pub fn sample(&self, lon: f32, lat: f32) -> (f32, f32) {
+        let x = (lon + 180.0) / 360.0 * self.width as f32 - 0.5;
+        let y = ((90.0 - lat) / 180.0 * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let wrap = |x: f32| (x as i32).rem_euclid(self.width as i32) as usize;
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let (y0, y1) = (y0 as usize, (y0 as usize + 1).min(self.height - 1));
+        let at = |values: &[f32]| {
+            let top = values[y0 * self.width + x0] * (1.0 - fx) + values[y0 * self.width + x1] * fx;
+            let bottom = values[y1 * self.width + x0] * (1.0 - fx) + values[y1 * self.width + x1] * fx;
+            top * (1.0 - fy) + bottom * fy
+        };
+        (at(&self.u), at(&self.v))
+    }
+
+    // RG32F texture with u in red and v in green, the shader does its own filtering with texelFetch
+    pub fn upload(&self) -> GLuint {
+        let texels: Vec<f32> = self.u.iter().zip(&self.v).flat_map(|(&u, &v)| [u, v]).collect();
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG32F as GLint, self.width as GLsizei, self.height as GLsizei, 0, gl::RG, gl::FLOAT, texels.as_ptr() as *const _);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        texture
+    }
+}
+
+// Text grid: the width and height, then a u v pair per cell, row by row from the north
+// lines starting with # are comments
+pub fn parse_wind(text: &str) -> Result<WindField, String> {
+    let mut tokens = text.lines().filter(|l| !l.trim_start().starts_with('#')).flat_map(|l| l.split_whitespace());
+    let mut size = || -> Result<usize, String> {
+        tokens.next().ok_or("missing grid size")?.parse().map_err(|e| format!("bad grid size: {}", e))
+    };
+    let (width, height) = (size()?, size()?);
+    if width == 0 || height == 0 {
+        return Err(format!("empty grid {}x{}", width, height));
+    }
+
+    let values = tokens.map(|t| t.parse::<f32>().map_err(|e| format!("bad value '{}': {}", t, e))).collect::<Result<Vec<_>, _>>()?;
+    if values.len() != width * height * 2 {
+        return Err(format!("expected {} values for a {}x{} grid, found {}", width * height * 2, width, height, values.len()));
+    }
+    let (u, v): (Vec<f32>, Vec<f32>) = values.chunks(2).map(|pair| (pair[0], pair[1])).unzip();
+    let max_speed = u.iter().zip(&v).map(|(u, v)| (u * u + v * v).sqrt()).fold(0.0, f32::max);
+    Ok(WindField { width, height, u, v, max_speed })
+}
+